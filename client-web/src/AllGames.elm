@@ -581,8 +581,8 @@ viewCover game =
         Just cover ->
             img
                 [ src ("/api/image/" ++ cover.id ++ "?size=Thumbnail")
-                , Attr.width cover.width
-                , Attr.height cover.height
+                , Attr.width cover.thumbnailWidth
+                , Attr.height cover.thumbnailHeight
                 , css [ height (pct 100), width auto ]
                 ]
                 []
@@ -598,8 +598,8 @@ viewScreenshots game =
             (\image ->
                 img
                     [ src ("/api/image/" ++ image.id ++ "?size=Thumbnail")
-                    , Attr.width image.width
-                    , Attr.height image.height
+                    , Attr.width image.thumbnailWidth
+                    , Attr.height image.thumbnailHeight
                     , css
                         [ height (pct 100)
                         , width auto
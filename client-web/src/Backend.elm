@@ -1,17 +1,20 @@
 module Backend exposing
     ( Catalog
+    , Dlc
+    , Extra
     , Game
     , Genre
     , Graphics(..)
     , Image
     , Multiplayer(..)
+    , StoreLink
     , Theme
     , getCatalog
     )
 
 import Http
 import Json.Decode as Decode exposing (Decoder, andThen, int, list, nullable, string)
-import Json.Decode.Pipeline exposing (required)
+import Json.Decode.Pipeline exposing (optional, required)
 import Set exposing (Set)
 import Url exposing (Url)
 
@@ -33,7 +36,9 @@ getCatalog msg =
 
 
 type alias Catalog =
-    { games : List Game
+    { siteTitle : String
+    , featured : List String
+    , games : List Game
     , genres : List Genre
     , themes : List Theme
     }
@@ -42,18 +47,26 @@ type alias Catalog =
 decodeCatalog : Decoder Catalog
 decodeCatalog =
     Decode.succeed Catalog
+        |> optional "site_title" string "Grifter"
+        |> optional "featured" (list string) []
         |> required "games" (list decodeGame)
         |> required "genres" (list decodeGenre)
         |> required "themes" (list decodeTheme)
 
 
 type alias Game =
-    { name : String
+    { id : Int
+    , name : String
     , slug : String
     , searchNames : List String
     , summary : Maybe String
     , genres : Set Int
     , themes : Set Int
+    , firstReleaseDate : Maybe Int
+    , totalRating : Maybe Float
+    , aggregatedRating : Maybe Float
+    , developers : List String
+    , publishers : List String
 
     -- Multiplayer
     , hasSinglePlayer : Bool
@@ -70,6 +83,7 @@ type alias Game =
     , graphics : Graphics
 
     -- Stores
+    , stores : List StoreLink
     , steam : Maybe Url
     , gog : Maybe Url
     , itch : Maybe Url
@@ -82,18 +96,27 @@ type alias Game =
     , path : String
     , sizeBytes : Int
     , version : Maybe String
+    , available : Bool
+    , extras : List Extra
+    , dlc : List Dlc
     }
 
 
 decodeGame : Decoder Game
 decodeGame =
     Decode.succeed Game
+        |> optional "id" int 0
         |> required "name" string
         |> required "slug" string
         |> required "search_names" (list string)
         |> required "summary" (nullable string)
         |> required "genres" (decodeSet int)
         |> required "themes" (decodeSet int)
+        |> optional "first_release_date" (nullable int) Nothing
+        |> optional "total_rating" (nullable Decode.float) Nothing
+        |> optional "aggregated_rating" (nullable Decode.float) Nothing
+        |> optional "developers" (list string) []
+        |> optional "publishers" (list string) []
         -- Multiplayer
         |> required "has_single_player" Decode.bool
         |> required "has_coop_campaign" Decode.bool
@@ -107,6 +130,7 @@ decodeGame =
         |> required "videos" (list string)
         |> required "graphics" decodeGraphics
         -- Stores
+        |> optional "stores" (list decodeStoreLink) []
         |> required "steam" (nullable decodeUrl)
         |> required "gog" (nullable decodeUrl)
         |> required "itch" (nullable decodeUrl)
@@ -118,12 +142,62 @@ decodeGame =
         |> required "path" string
         |> required "size_bytes" int
         |> required "version" (nullable string)
+        |> optional "available" Decode.bool True
+        |> optional "extras" (list decodeExtra) []
+        |> optional "dlc" (list decodeDlc) []
+
+
+type alias Extra =
+    { label : String
+    , kind : String
+    , sizeBytes : Int
+    , available : Bool
+    }
+
+
+decodeExtra : Decoder Extra
+decodeExtra =
+    Decode.succeed Extra
+        |> required "label" string
+        |> required "type" string
+        |> required "size_bytes" int
+        |> required "available" Decode.bool
+
+
+type alias Dlc =
+    { label : String
+    , sizeBytes : Int
+    , available : Bool
+    }
+
+
+decodeDlc : Decoder Dlc
+decodeDlc =
+    Decode.succeed Dlc
+        |> required "label" string
+        |> required "size_bytes" int
+        |> required "available" Decode.bool
+
+
+type alias StoreLink =
+    { name : String
+    , url : Url
+    }
+
+
+decodeStoreLink : Decoder StoreLink
+decodeStoreLink =
+    Decode.succeed StoreLink
+        |> required "name" string
+        |> required "url" decodeUrl
 
 
 type alias Image =
     { id : String
     , width : Int
     , height : Int
+    , thumbnailWidth : Int
+    , thumbnailHeight : Int
     }
 
 
@@ -133,6 +207,8 @@ decodeImage =
         |> required "id" string
         |> required "width" int
         |> required "height" int
+        |> required "thumbnail_width" int
+        |> required "thumbnail_height" int
 
 
 type alias Theme =
@@ -205,11 +281,10 @@ decodeGraphics =
                     "Pixelated" ->
                         Decode.succeed Pixelated
 
-                    "Smooth" ->
+                    -- Any other graphics style configured on the server (like "Low-poly")
+                    -- is rendered the same as smooth graphics.
+                    _ ->
                         Decode.succeed Smooth
-
-                    other ->
-                        Decode.fail ("Invalid value " ++ other ++ " for Graphics")
             )
 
 
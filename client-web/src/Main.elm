@@ -110,7 +110,7 @@ updateApp msg app =
         UrlChanged url ->
             case routeFromUrl url of
                 Game slug ->
-                    case find (\g -> g.slug == slug) app.catalog.games of
+                    case find (isGame slug) app.catalog.games of
                         Just game ->
                             ( Loaded { app | url = url, page = SingleGame game }, reviveViewport app url )
 
@@ -187,7 +187,7 @@ updateLoading msg { key, url } =
                     )
 
                 Game slug ->
-                    case find (\g -> g.slug == slug) catalog.games of
+                    case find (isGame slug) catalog.games of
                         Just game ->
                             ( Loaded
                                 { key = key
@@ -241,6 +241,16 @@ reviveViewport app url =
             Task.perform (\_ -> ResetViewport) (Browser.Dom.setViewport 0 0)
 
 
+
+-- Game urls can use either the slug or IGDB's id, which doesn't change when the slug does.
+-- Games IGDB doesn't know have an id of 0.
+
+
+isGame : String -> Game -> Bool
+isGame slugOrId game =
+    game.slug == slugOrId || (game.id /= 0 && String.fromInt game.id == slugOrId)
+
+
 find : (a -> Bool) -> List a -> Maybe a
 find isGood list =
     List.filter isGood list |> List.head
@@ -271,12 +281,12 @@ view model =
         Loaded loaded ->
             case loaded.page of
                 AllGames ->
-                    { title = "Grifter"
+                    { title = loaded.catalog.siteTitle
                     , body = [ AllGames.view loaded.catalog loaded.allGames |> Html.Styled.map MsgAllGames ]
                     }
 
                 SingleGame game ->
-                    { title = game.name ++ " - " ++ "Grifter"
+                    { title = game.name ++ " - " ++ loaded.catalog.siteTitle
                     , body = [ SingleGame.view loaded.catalog game |> Html.Styled.map MsgSingleGame ]
                     }
 
@@ -9,6 +9,7 @@ import Set
 import Shared exposing (inter, rgbaFromColor)
 import Svg.Styled as Svg
 import Svg.Styled.Attributes as SvgAttr
+import Time
 import Url exposing (Url)
 
 
@@ -27,16 +28,8 @@ view catalog game =
 
         stores : List ( String, Url )
         stores =
-            List.filterMap identity
-                [ Maybe.map (\url -> ( "Steam", url )) game.steam
-                , Maybe.map (\url -> ( "GOG", url )) game.gog
-                , Maybe.map (\url -> ( "Itch.io", url )) game.itch
-                , Maybe.map (\url -> ( "Epic Games", url )) game.epic
-                , Maybe.map (\url -> ( "Google Play", url )) game.googlePlay
-                , Maybe.map (\url -> ( "Apple iPhone", url )) game.applePhone
-                , Maybe.map (\url -> ( "Apple iPad", url )) game.applePad
-                , Maybe.map (\url -> ( "IGDB", url )) (Url.fromString ("https://www.igdb.com/games/" ++ game.slug))
-                ]
+            List.map (\store -> ( store.name, store.url )) game.stores
+                ++ List.filterMap identity [ Maybe.map (\url -> ( "IGDB", url )) (Url.fromString ("https://www.igdb.com/games/" ++ game.slug)) ]
 
         modes : List String
         modes =
@@ -122,7 +115,9 @@ view catalog game =
         , div [ id "info", css [ property "grid-row" "2", lineHeight (num 1.7) ] ]
             [ viewCover game
             , viewTags genres modes stores
+            , viewFacts game
             , viewSummary game
+            , viewExtras game
             ]
         , div
             [ id "media"
@@ -157,27 +152,38 @@ viewAllGames =
 
 viewDownload : Backend.Game -> Html msg
 viewDownload game =
+    let
+        buttonStyle =
+            [ border3 (px 2) solid Shared.black
+            , borderRadius (px 8)
+            , padding2 (px 12) (px 20)
+            , color Shared.black
+            , textDecoration unset
+            , display inlineFlex
+            , alignItems center
+            , fontWeight (int 500)
+            , boxShadow5 zero (px 1) (px 2) (px 1) (rgba 0 0 0 0.24)
+            , marginLeft (px 20)
+            ]
+    in
     div [ id "download", css [ displayFlex, alignItems baseline ] ]
         [ span [ css [ marginLeft (px 4) ] ] [ text (formatBytes game.sizeBytes) ]
-        , a
-            [ Attr.href ("/api/download/" ++ game.slug)
-            , Attr.download ""
-            , css
-                [ border3 (px 2) solid Shared.black
-                , borderRadius (px 8)
-                , padding2 (px 12) (px 20)
-                , color Shared.black
-                , textDecoration unset
-                , display inlineFlex
-                , alignItems center
-                , fontWeight (int 500)
-                , boxShadow5 zero (px 1) (px 2) (px 1) (rgba 0 0 0 0.24)
-                , marginLeft (px 20)
+        , if game.available then
+            a
+                [ Attr.href ("/api/download/" ++ game.slug)
+                , Attr.download ""
+                , css buttonStyle
                 ]
-            ]
-            [ div [ css [ marginRight (ch 0.4), lineHeight zero ] ] [ viewWindowsLogo [ SvgAttr.height "1em" ] ]
-            , text "Download"
-            ]
+                [ div [ css [ marginRight (ch 0.4), lineHeight zero ] ] [ viewWindowsLogo [ SvgAttr.height "1em" ] ]
+                , text "Download"
+                ]
+
+          else
+            span
+                [ css (buttonStyle ++ [ opacity (num 0.4), boxShadow none, cursor notAllowed ])
+                , Attr.title "The file for this game can't be found on the server right now."
+                ]
+                [ text "Unavailable" ]
         ]
 
 
@@ -200,7 +206,7 @@ viewCover game =
         Just cover ->
             img
                 [ id "cover"
-                , Attr.src ("/api/image/" ++ cover.id ++ "?size=Original")
+                , Attr.src ("/api/image/" ++ cover.id ++ "?size=Medium")
                 , Attr.width cover.width
                 , Attr.height cover.height
                 , css [ display block, width (pct 100), height auto, marginBottom (em 1) ]
@@ -235,9 +241,50 @@ viewTags genres modes stores =
         ]
 
 
+viewFacts : Backend.Game -> Html msg
+viewFacts game =
+    let
+        released =
+            game.firstReleaseDate
+                |> Maybe.map (\seconds -> Time.toYear Time.utc (Time.millisToPosix (seconds * 1000)))
+                |> Maybe.map (\year -> "Released in " ++ String.fromInt year)
+
+        companies label names =
+            if List.isEmpty names then
+                Nothing
+
+            else
+                Just (label ++ " " ++ String.join ", " names)
+
+        rating =
+            game.totalRating
+                |> Maybe.map (\score -> "Rated " ++ String.fromInt (round score) ++ " out of 100")
+
+        facts =
+            List.filterMap identity
+                [ released
+                , companies "Developed by" game.developers
+                , companies "Published by" game.publishers
+                , rating
+                ]
+    in
+    div [ id "facts", css [ marginBottom (em 1), color (hsl 0 0 0.32) ] ]
+        (List.map (\fact -> div [] [ text fact ]) facts)
+
+
 viewSummary : Backend.Game -> Html msg
 viewSummary game =
-    p [ id "summary", css [ color (hsl 0 0 0.32), marginBottom (em 1), fontWeight (int 300) ] ]
+    p
+        [ id "summary"
+        , css
+            [ color (hsl 0 0 0.32)
+            , marginBottom (em 1)
+            , fontWeight (int 300)
+
+            -- Keep the paragraph breaks in summaries, like notes added in the config.
+            , property "white-space" "pre-line"
+            ]
+        ]
         [ case game.summary of
             Just summary ->
                 text summary
@@ -247,6 +294,92 @@ viewSummary game =
         ]
 
 
+viewExtras : Backend.Game -> Html msg
+viewExtras game =
+    let
+        -- Extras are downloaded by their place in the list, so hang on to it while grouping.
+        extras =
+            List.indexedMap Tuple.pair game.extras
+
+        kinds =
+            List.foldl
+                (\( _, extra ) seen ->
+                    if List.member extra.kind seen then
+                        seen
+
+                    else
+                        seen ++ [ extra.kind ]
+                )
+                []
+                extras
+
+        viewFile path ( index, file ) =
+            if file.available then
+                a
+                    [ Attr.href ("/api/download/" ++ game.slug ++ path ++ String.fromInt index)
+                    , Attr.download ""
+                    , css [ display block, color Shared.black ]
+                    ]
+                    [ text file.label
+                    , span [ css [ marginLeft (ch 0.6), color (hsl 0 0 0.5) ] ] [ text (formatBytes file.sizeBytes) ]
+                    ]
+
+            else
+                span
+                    [ css [ display block, opacity (num 0.4) ]
+                    , Attr.title "The file for this can't be found on the server right now."
+                    ]
+                    [ text file.label ]
+
+        viewGroup title files =
+            div [ css [ marginBottom (em 1) ] ]
+                (div [ css [ fontWeight (int 600) ] ] [ text title ] :: files)
+
+        viewKind kind =
+            viewGroup kind
+                (List.map (viewFile "/extras/") (List.filter (\( _, extra ) -> extra.kind == kind) extras))
+
+        viewDlc =
+            if List.isEmpty game.dlc then
+                []
+
+            else
+                [ viewGroup "DLC" (List.map (viewFile "/dlc/") (List.indexedMap Tuple.pair game.dlc)) ]
+
+        -- The game, its extras, and its DLC zipped together. Missing files are left out of the zip.
+        downloadAll =
+            if List.isEmpty game.extras && List.isEmpty game.dlc then
+                text ""
+
+            else
+                a
+                    [ Attr.href ("/api/download-all/" ++ game.slug)
+                    , Attr.download ""
+                    , css [ display block, color Shared.black, fontWeight (int 600) ]
+                    ]
+                    [ text "Download everything"
+                    , span [ css [ marginLeft (ch 0.6), color (hsl 0 0 0.5), fontWeight normal ] ]
+                        [ text (formatBytes (totalSize game)) ]
+                    ]
+    in
+    div [ id "extras" ] (viewDlc ++ List.map viewKind kinds ++ [ downloadAll ])
+
+
+totalSize : Backend.Game -> Int
+totalSize game =
+    let
+        availableSize file =
+            if file.available then
+                file.sizeBytes
+
+            else
+                0
+    in
+    availableSize game
+        + List.sum (List.map availableSize game.extras)
+        + List.sum (List.map availableSize game.dlc)
+
+
 viewScreenshot : Backend.Game -> Backend.Image -> Html msg
 viewScreenshot game screenshot =
     div
@@ -258,7 +391,7 @@ viewScreenshot game screenshot =
             ]
         ]
         [ img
-            [ Attr.src ("/api/image/" ++ screenshot.id ++ "?size=Original")
+            [ Attr.src ("/api/image/" ++ screenshot.id ++ "?size=Medium")
             , Attr.width screenshot.width
             , Attr.height screenshot.height
             , css
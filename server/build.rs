@@ -109,6 +109,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     fs_extra::dir::copy(client_path.join("assets"), out_dir.join("assets"), &options)?;
     fs::copy(client_path.join("index.html"), out_dir.join("index.html"))?;
     fs::copy(client_path.join("favicon.ico"), out_dir.join("favicon.ico"))?;
+    fs::copy(client_path.join("favicon.png"), out_dir.join("favicon.png"))?;
     // TODO: if the program throws an exception here, elm.js is left in client_web
 
     // Copy elm app
@@ -135,10 +136,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         })
         .collect();
 
+    // Assets are compressed here instead of at startup, into their own dirs so they don't get
+    // mistaken for assets themselves. Each entry in CLIENT_WEB is (url, bytes, gzipped bytes,
+    // brotli bytes, hash of the gzipped bytes).
+    let gz_dir = PathBuf::from(env::var("OUT_DIR")?).join("client-web-gz");
+    let br_dir = PathBuf::from(env::var("OUT_DIR")?).join("client-web-br");
     let mut client_web = String::new();
     writeln!(
         &mut client_web,
-        "pub const CLIENT_WEB: [(&str, &[u8]); {}] = [",
+        "pub type Asset = (&'static str, &'static [u8], &'static [u8], &'static [u8], &'static str);"
+    )?;
+    writeln!(
+        &mut client_web,
+        "pub const CLIENT_WEB: [Asset; {}] = [",
         assets.len()
     )?;
     for asset in assets.iter() {
@@ -150,10 +160,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .map(|c| if c == '\\' { '/' } else { c })
             .collect::<String>();
 
+        let bytes = fs::read(asset)?;
+        let gzipped = gzip(&bytes)?;
+        let gz_path = gz_dir.join(format!("{}.gz", url.trim_start_matches('/')));
+        fs::create_dir_all(gz_path.parent().unwrap())?;
+        fs::write(&gz_path, &gzipped)?;
+        let br_path = br_dir.join(format!("{}.br", url.trim_start_matches('/')));
+        fs::create_dir_all(br_path.parent().unwrap())?;
+        fs::write(&br_path, brotli(&bytes)?)?;
+
         writeln!(
             &mut client_web,
-            r##"    ("{url}", include_bytes!(concat!(env!("OUT_DIR"), "/client-web{url}"))),"##,
+            r##"    ("{url}", include_bytes!(concat!(env!("OUT_DIR"), "/client-web{url}")), include_bytes!(concat!(env!("OUT_DIR"), "/client-web-gz{url}.gz")), include_bytes!(concat!(env!("OUT_DIR"), "/client-web-br{url}.br")), "{hash}"),"##,
             url = url,
+            hash = encoded_hash(&gzipped),
         )?;
     }
     writeln!(&mut client_web, "];")?;
@@ -162,6 +182,39 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Must match `api::encoded_hash`, and `api::gzip` and `api::brotli` at the levels
+// `api::compressed_asset` uses, so build-time and runtime assets agree.
+fn encoded_hash(bytes: &[u8]) -> String {
+    use blake2::digest::{Update, VariableOutput};
+    use blake2::VarBlake2b;
+
+    let mut hash = String::new();
+    let mut hasher = VarBlake2b::new(10).unwrap();
+    hasher.update(bytes);
+    hasher.finalize_variable(|hash_bytes| {
+        let config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        hash = base64::encode_config(hash_bytes, config);
+    });
+    hash
+}
+
+fn gzip(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+fn brotli(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 9, 22);
+    encoder.write_all(bytes)?;
+    Ok(encoder.into_inner())
+}
+
 fn print_how_to_install_uglifyjs() {
     eprintln!("I tried to run 'uglifyjs' but couldn't find it!");
     eprintln!("The quickest way to fix this is to install it from npm using this command:");
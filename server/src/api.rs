@@ -1,112 +1,451 @@
+use crate::checksum::Checksums;
 use crate::client_web;
-use crate::config::Config;
-use crate::game::Game;
+use crate::config::{self, Config};
+use crate::game::{self, normalize_slug, Game};
 use crate::igdb;
-use crate::twitch;
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::metadata::MetadataProvider;
+use crate::relay;
+use crate::search;
+use crate::shutdown;
+use crate::store::Store;
+use crate::zip;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use image::imageops::FilterType;
 use image::GenericImageView;
-use rouille::{extension_to_mime, router, Request, Response, Server};
-use serde::Serialize;
-use std::collections::HashMap;
+use notify_debouncer_mini::notify::{RecursiveMode, Watcher};
+use notify_debouncer_mini::{new_debouncer, DebouncedEventKind};
+use rouille::{extension_to_mime, router, Request, Response, ResponseBody, Server};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Clone)]
 struct Model {
     catalog: Catalog,
-    catalog_gz: GzippedAsset,
-    assets_gz: HashMap<&'static str, GzippedAsset>,
+    // The whole catalog and each game on its own, by slug. With a catalog database they're read
+    // from there instead, so they aren't kept here. See `store::Store`.
+    catalog_gz: Option<CompressedAsset>,
+    compact_catalog_gz: CompressedAsset,
+    games_gz: HashMap<String, CompressedAsset>,
+    // Each game's page by slug, with the origin it was built for. They're built on the first
+    // visit instead of up front since the Open Graph tags need the origin. See `get_game_page`.
+    game_pages: Arc<GamePages>,
+    browse_gz: CompressedAsset,
+    catalog_csv_gz: CompressedAsset,
+    manifest_gz: CompressedAsset,
+    assets_gz: HashMap<&'static str, CompressedAsset>,
+    search_threshold: f64,
+    behind_proxy: bool,
+    warnings: Vec<IndexWarning>,
+}
+
+type GamePages = Mutex<HashMap<String, (String, Arc<CompressedAsset>)>>;
+
+// A config or indexing problem, with the slugs and paths involved so it can be tracked down.
+#[derive(Clone, Serialize)]
+pub struct IndexWarning {
+    kind: &'static str,
+    message: String,
+    slugs: Vec<String>,
+    paths: Vec<PathBuf>,
+}
+
+impl From<&config::Warning> for IndexWarning {
+    fn from(warning: &config::Warning) -> Self {
+        let (slugs, paths) = match warning {
+            config::Warning::ConflictingGames(games) => (
+                games.iter().map(|game| game.slug()).collect(),
+                games.iter().map(|game| game.path.clone()).collect(),
+            ),
+            config::Warning::MissingExe(game) => (vec![game.slug()], vec![game.path.clone()]),
+            config::Warning::MissingExtra(game, extra) => {
+                (vec![game.slug()], vec![extra.path.clone()])
+            }
+            config::Warning::MissingDlc(game, dlc) => (vec![game.slug()], vec![dlc.path.clone()]),
+            config::Warning::UnusedExe(path) => (vec![], vec![PathBuf::from(path)]),
+            config::Warning::PrivilegedPort(_) => (vec![], vec![]),
+        };
+        IndexWarning {
+            kind: warning.code(),
+            message: warning.to_string(),
+            slugs,
+            paths,
+        }
+    }
+}
+
+impl From<&game::Warning> for IndexWarning {
+    fn from(warning: &game::Warning) -> Self {
+        let (kind, slugs) = match warning {
+            game::Warning::MissingSlug(slug) => ("missing_slug", vec![slug.clone()]),
+            game::Warning::IgdbFetchFailed(slugs, _) => ("fetch_failed", slugs.clone()),
+            game::Warning::KeywordFetchFailed(_) => ("keyword_fetch_failed", vec![]),
+            game::Warning::MissingFeatured(slug) => ("missing_featured", vec![slug.clone()]),
+            game::Warning::UnexpectedGame(slug) => ("unexpected_game", vec![slug.clone()]),
+            game::Warning::DuplicateGame(slug) => ("duplicate_game", vec![slug.clone()]),
+            game::Warning::MetadataFailed(slug, _) => ("metadata_failed", vec![slug.clone()]),
+            game::Warning::CoverFailed(slug, _) => ("cover_failed", vec![slug.clone()]),
+        };
+        IndexWarning {
+            kind,
+            message: warning.to_string(),
+            slugs,
+            paths: vec![],
+        }
+    }
 }
 
 #[derive(Clone)]
-struct GzippedAsset {
+struct CompressedAsset {
     mime: &'static str,
-    bytes: Vec<u8>,
+    gzipped: Vec<u8>,
+    brotli: Vec<u8>,
+    // Hash of the gzipped bytes. The other encodings add a suffix so their etags differ.
     hash: String,
+    // Kept around for clients that don't accept gzip or brotli.
+    uncompressed: Vec<u8>,
 }
 
 #[derive(Clone, Serialize)]
 struct Catalog {
+    site_title: String,
+    site_description: Option<String>,
+    featured: Vec<String>,
+    // Unix timestamp of when the games were last indexed. Left out here and added when the
+    // catalog is served, see `catalog_gz`.
+    #[serde(skip)]
+    indexed_at: u64,
+    game_count: usize, // Only counts listed games.
+    // Every game, including hidden ones. Hidden games are only left out of the JSON.
+    #[serde(serialize_with = "serialize_listed_games")]
     games: Vec<Game>,
     genres: Vec<igdb::Genre>,
     themes: Vec<igdb::Theme>,
 }
 
-pub fn start(
+fn serialize_listed_games<S: serde::Serializer>(
+    games: &[Game],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(games.iter().filter(|game| !game.hidden))
+}
+
+fn build_model(
     config: &Config,
-    last_request: &mut std::time::Instant,
+    metadata: &mut dyn MetadataProvider,
     games: Vec<Game>,
-) -> std::io::Result<()> {
-    let access_token = twitch::authenticate(&config.twitch_client_id, &config.twitch_client_secret)
-        .unwrap()
-        .access_token;
-
-    let mut genres =
-        igdb::get_genres(&config.twitch_client_id, &access_token, last_request).unwrap();
+    mut warnings: Vec<IndexWarning>,
+    store: &Option<Store>,
+) -> Model {
+    // Without genres and themes the games can't be filtered by them, but they're still there.
+    let mut fetch_failed = |what: &str, err: igdb::Error| {
+        let message = format!("failed to fetch {} from IGDB: {}", what, err);
+        warn!("Warning: {}", message);
+        warnings.push(IndexWarning {
+            kind: "fetch_failed",
+            message,
+            slugs: vec![],
+            paths: vec![],
+        });
+    };
+    let mut genres = metadata.fetch_genres().unwrap_or_else(|err| {
+        fetch_failed("genres", err);
+        Vec::new()
+    });
+    let genre_names = config.genre_names();
     for genre in genres.iter_mut() {
-        // The names for some of these genres are ugly/verbose. Manually fixing them here.
-        match genre.id {
-            25 => genre.name = "Hack and slash".to_string(),
-            16 => genre.name = "Turn-based strategy".to_string(),
-            11 => genre.name = "Real Time Strategy".to_string(),
-            _ => {}
+        let name = genre_names
+            .get(&genre.id.to_string())
+            .or_else(|| genre_names.get(&genre.slug));
+        if let Some(name) = name {
+            genre.name = name.clone();
         }
     }
+    // Without any genres, fetching them failed and that's already been warned about.
+    let is_genre = |key: &String| {
+        genres
+            .iter()
+            .any(|g| &g.id.to_string() == key || &g.slug == key)
+    };
+    let unknown_genres: Vec<&String> = if genres.is_empty() {
+        Vec::new()
+    } else {
+        genre_names.keys().filter(|key| !is_genre(key)).collect()
+    };
     genres.drain_filter(|genre| !games.iter().any(|game| game.genres.contains(&genre.id)));
     genres.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let mut themes =
-        igdb::get_themes(&config.twitch_client_id, &access_token, last_request).unwrap();
+    let mut themes = metadata.fetch_themes().unwrap_or_else(|err| {
+        fetch_failed("themes", err);
+        Vec::new()
+    });
     themes.drain_filter(|theme| !games.iter().any(|game| game.themes.contains(&theme.id)));
     themes.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let model = {
-        let mut assets_gz = HashMap::new();
-        for (url, uncompressed) in client_web::CLIENT_WEB {
-            let compressed = gzip(uncompressed).unwrap();
-            let mime = PathBuf::from(url)
-                .extension()
-                .and_then(OsStr::to_str)
-                .map(extension_to_mime)
-                .unwrap_or("application/octet-stream");
-            let hash = encoded_hash(&compressed);
-            let asset = GzippedAsset {
-                mime,
-                bytes: compressed,
-                hash,
-            };
-            assets_gz.insert(url, asset);
-        }
+    for genre in unknown_genres {
+        let message = format!("genre_overrides has {:?}, which isn't an IGDB genre", genre);
+        warn!("Warning: {}", message);
+        warnings.push(IndexWarning {
+            kind: "unknown_genre",
+            message,
+            slugs: vec![],
+            paths: vec![],
+        });
+    }
 
-        let catalog = Catalog {
-            games,
-            genres,
-            themes,
-        };
-        let catalog_json = serde_json::to_vec(&catalog).unwrap();
-        let catalog_compressed = gzip(&catalog_json).unwrap();
-        let catalog_gz = GzippedAsset {
-            mime: extension_to_mime("json"),
-            hash: encoded_hash(&catalog_compressed),
-            bytes: catalog_compressed,
+    let mut assets_gz = HashMap::new();
+    for (url, uncompressed, gzipped, brotli, hash) in client_web::CLIENT_WEB {
+        let mime = PathBuf::from(url)
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(extension_to_mime)
+            .unwrap_or("application/octet-stream");
+        // Assets are compressed at build time, except for the index which is branded from the
+        // config first.
+        let asset = if url == "/index.html" {
+            compressed_asset(mime, &branded_index(uncompressed, config))
+        } else {
+            CompressedAsset {
+                mime,
+                gzipped: gzipped.to_vec(),
+                brotli: brotli.to_vec(),
+                hash: hash.to_string(),
+                uncompressed: uncompressed.to_vec(),
+            }
         };
+        assets_gz.insert(url, asset);
+    }
 
-        Model {
-            catalog,
-            catalog_gz,
-            assets_gz,
+    // Home screen icons for the web app manifest, scaled up from the favicon. A favicon from the
+    // config replaces the built in one everywhere.
+    let custom_favicon = config
+        .favicon
+        .as_ref()
+        .and_then(|path| match image::open(path) {
+            Ok(favicon) => Some(favicon),
+            Err(err) => {
+                warn!(
+                    "Using the default favicon, {:?} couldn't be read: {}",
+                    path, err
+                );
+                None
+            }
+        });
+    let default_favicon = client_web::CLIENT_WEB
+        .iter()
+        .find(|(url, ..)| *url == "/favicon.png")
+        .map(|(_, favicon, ..)| image::load_from_memory(favicon).unwrap());
+    let icons = match (custom_favicon, default_favicon) {
+        // It could be any kind of picture, so it's scaled smoothly.
+        (Some(favicon), _) => Some((favicon, FilterType::Lanczos3, &FAVICONS[..])),
+        // The built in favicon is pixel art, so it's scaled without smoothing.
+        (None, Some(favicon)) => Some((favicon, FilterType::Nearest, APP_ICONS)),
+        (None, None) => None,
+    };
+    if let Some((favicon, filter, icons)) = icons {
+        for &(url, size) in icons {
+            let icon = app_icon(&favicon, url, size, filter).unwrap();
+            let mime = extension_to_mime(url.rsplit('.').next().unwrap_or(""));
+            assets_gz.insert(url, compressed_asset(mime, &icon));
         }
+    }
+    // Urls in the manifest are relative to the manifest itself, so installing the app still works
+    // when it's served from a subpath behind a reverse proxy.
+    let manifest = serde_json::json!({
+        "name": config.site_title,
+        "short_name": config.site_title,
+        "start_url": ".",
+        "display": "standalone",
+        "background_color": "#fcfbf9",
+        "icons": APP_ICONS
+            .iter()
+            .map(|&(url, size)| serde_json::json!({
+                "src": url.trim_start_matches('/'),
+                "sizes": format!("{}x{}", size, size),
+                "type": "image/png",
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let manifest_gz = compressed_asset(
+        "application/manifest+json",
+        &serde_json::to_vec(&manifest).unwrap(),
+    );
+
+    // Featured slugs in config order, skipping any that didn't resolve to a listed game.
+    let featured = config
+        .featured
+        .iter()
+        .filter_map(|slug| {
+            let slug = normalize_slug(slug);
+            games
+                .iter()
+                .find(|game| !game.hidden && normalize_slug(&game.slug) == slug)
+                .map(|game| game.slug.clone())
+        })
+        .collect();
+
+    let indexed_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let catalog = Catalog {
+        site_title: config.site_title.clone(),
+        site_description: config.site_description.clone(),
+        featured,
+        indexed_at,
+        game_count: games.iter().filter(|game| !game.hidden).count(),
+        games,
+        genres,
+        themes,
+    };
+    let (catalog_gz, games_gz) = match store {
+        Some(_) => (None, HashMap::new()),
+        None => (Some(catalog_gz(&catalog)), games_gz(&catalog)),
+    };
+    let compact_catalog_gz = compact_catalog_gz(&catalog);
+    let browse_gz = browse_gz(&catalog);
+    let catalog_csv_gz = catalog_csv_gz(&catalog);
+
+    Model {
+        catalog,
+        catalog_gz,
+        compact_catalog_gz,
+        games_gz,
+        game_pages: Default::default(),
+        browse_gz,
+        catalog_csv_gz,
+        manifest_gz,
+        assets_gz,
+        search_threshold: config.search_threshold,
+        behind_proxy: config.behind_proxy,
+        warnings,
+    }
+}
+
+// Everything the request handlers share. The model is swapped out wholesale when the catalog
+// is rebuilt, so handlers grab their own `Arc` of it and never see a half-built catalog.
+struct Shared {
+    model: RwLock<Arc<Model>>,
+    metadata: Mutex<Box<dyn MetadataProvider + Send>>,
+    // Taken on shutdown, which closes the channel so the prefetcher finishes what's queued and
+    // stops.
+    prefetch: Mutex<Option<Sender<String>>>,
+    checksums: Sender<PathBuf>,
+    // Images that couldn't be cached. They're left out of the catalog so clients don't try to
+    // show them.
+    failed_images: Mutex<HashSet<String>>,
+    cache_usage: Arc<CacheUsage>,
+    admin_token: Option<String>,
+    // How many images were queued when the server started, and the fraction of them that has to
+    // be cached before the server reports itself as ready.
+    initial_images: usize,
+    ready_after_prefetch: f64,
+    is_ready: AtomicBool,
+    started: Instant,
+    // Answers searches instead of the catalog in memory when `catalog_database` is set.
+    store: Option<Store>,
+}
+
+impl Shared {
+    fn model(&self) -> Arc<Model> {
+        Arc::clone(&self.model.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn prefetch(&self) -> Option<Sender<String>> {
+        lock(&self.prefetch).clone()
+    }
+}
+
+pub fn start(
+    config: &Config,
+    mut metadata: Box<dyn MetadataProvider + Send>,
+    games: Vec<Game>,
+    warnings: Vec<IndexWarning>,
+    prefetch: Prefetcher,
+    cache_usage: Arc<CacheUsage>,
+) -> std::io::Result<()> {
+    let store = match &config.catalog_database {
+        Some(path) => Some(Store::open(path)?),
+        None => None,
     };
+    let model = build_model(config, metadata.as_mut(), games, warnings, &store);
+    update_store(&store, &model.catalog.games);
+    let Prefetcher {
+        queue: prefetch,
+        pool: prefetch_pool,
+        failures: prefetch_failures,
+    } = prefetch;
+    let initial_images = queue_images(&prefetch, &model.catalog.games);
+    let (checksums, checksum_jobs) = unbounded();
+    queue_checksums(&checksums, &model.catalog.games);
+    let shared = Arc::new(Shared {
+        model: RwLock::new(Arc::new(model)),
+        metadata: Mutex::new(metadata),
+        prefetch: Mutex::new(Some(prefetch)),
+        checksums,
+        failed_images: Mutex::new(HashSet::new()),
+        cache_usage,
+        admin_token: config.admin_token.clone(),
+        initial_images,
+        ready_after_prefetch: config.ready_after_prefetch,
+        is_ready: AtomicBool::new(false),
+        started: Instant::now(),
+        store,
+    });
+
+    let reconciler = Arc::clone(&shared);
+    std::thread::spawn(move || drop_failed_images(&reconciler, prefetch_failures));
+    let hasher = Arc::clone(&shared);
+    std::thread::spawn(move || fill_checksums(&hasher, checksum_jobs));
+    if let Some(max_bytes) = config.cache_max_bytes {
+        let usage = Arc::clone(&shared.cache_usage);
+        std::thread::spawn(move || loop {
+            // Waits first so the images queued on startup aren't removed before they're used.
+            std::thread::sleep(std::time::Duration::from_secs(5 * 60));
+            evict_images(&usage, max_bytes);
+        });
+    }
 
-    if config.https {
+    // There's nothing new to fetch when the metadata comes from a file instead of IGDB. The
+    // interval is only read on startup, so changing it needs a restart.
+    if let (Some(hours), None) = (config.reindex_interval_hours, &config.metadata_file) {
+        let reindexer = Arc::clone(&shared);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(hours * 60 * 60));
+            // Failures are already logged, and the next run gets another shot.
+            let _ = reload(&reindexer);
+        });
+    }
+
+    if config.watch_for_changes {
+        let watcher = Arc::clone(&shared);
+        let paths = watched_paths(config);
+        std::thread::spawn(move || watch_for_changes(&watcher, paths));
+    }
+
+    // A Unix socket only takes plain http from a proxy on the same machine, so there's no
+    // https to redirect to.
+    let https = config.https && config.unix_socket.is_none();
+    if https {
         // Since we're going to start an https server, we'll want to redirect all http traffic
         // to https. So we'll start an http server whose sole purpose is to redirect to the
         // https server.
         let http_port = config.http_port;
         let https_port = config.https_port;
-        let address = config.address.clone();
+        let address = config.address;
         std::thread::spawn(move || {
             rouille::start_server((address, http_port), move |request| {
                 match request.header("host") {
@@ -131,279 +470,2789 @@ pub fn start(
         });
     }
 
-    let is_https_enabled = config.https;
+    let is_https_enabled = https;
+    let behind_proxy = config.behind_proxy;
+    let allowed_cidrs = config.allowed_cidrs.clone();
+    let basic_auth = config.auth.as_ref().map(|auth| BasicAuth {
+        users: auth.users.clone(),
+        verified: Mutex::new(HashSet::new()),
+    });
 
+    // Responses keep being sent after the handler returns, so they're counted until their body is
+    // done. Shutting down waits on them.
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let handler_in_flight = Arc::clone(&in_flight);
+    let cache_usage = Arc::clone(&shared.cache_usage);
+    let on_shutdown = Arc::clone(&shared);
+    let save_cache_usage = config.cache_max_bytes.is_some();
+    // A timeout of zero can't be set on a socket, so it's taken as off.
+    let idle_timeout = config
+        .idle_timeout_seconds
+        .filter(|&seconds| seconds > 0)
+        .map(std::time::Duration::from_secs);
+    let pool_size = 8 * num_cpus::get();
+    let peers = if config.unix_socket.is_some() || idle_timeout.is_some() {
+        // Connections past what the pool can serve at once wait for one of its threads, so up to
+        // four times as many are relayed to wait there. See `relay::Peers::new`.
+        Some(Arc::new(relay::Peers::new(4 * pool_size)))
+    } else {
+        None
+    };
+    let handler_peers = peers.clone();
     let handler = move |request: &Request| -> Response {
-        println!(
-            "{origin} to {protocol}://{host}{path}",
-            origin = request.remote_addr().ip(),
-            host = request.header("host").unwrap_or(""),
-            protocol = if is_https_enabled { "https" } else { "http" },
-            path = request.raw_url()
-        );
-
-        match model.assets_gz.get(request.raw_url()) {
-            Some(asset) => return get_asset(request, asset),
-            None => {}
+        let peer = match &handler_peers {
+            Some(peers) => match peers.get(request.remote_addr()) {
+                Some(peer) => peer,
+                // Only the relay is meant to reach the server's loopback port.
+                None => return Response::text("Forbidden").with_status_code(403),
+            },
+            None => request.remote_addr().ip(),
+        };
+        let remote_addr = peer.to_string();
+        if !allowed_cidrs.is_empty() {
+            let is_allowed = client_ip(request, peer, behind_proxy)
+                .map(|ip| allowed_cidrs.iter().any(|cidr| cidr.contains(ip)))
+                .unwrap_or(false);
+            if !is_allowed {
+                info!("{} isn't in allowed_cidrs, rejected", remote_addr);
+                return Response::text("Forbidden").with_status_code(403);
+            }
+        }
+        if let Some(basic_auth) = &basic_auth {
+            // A request only has one authorization header, so the admin token has to be
+            // enough on its own for the admin endpoints.
+            let is_admin = shared
+                .admin_token
+                .as_ref()
+                .map_or(false, |token| is_authorized(request, token));
+            if !is_health_check(&request.url()) && !is_admin && !basic_auth.allows(request) {
+                return Response::text("unauthorized")
+                    .with_status_code(401)
+                    .with_unique_header(
+                        "www-authenticate",
+                        "Basic realm=\"grifter\", charset=\"UTF-8\"",
+                    );
+            }
         }
 
-        router!(request,
-            (GET) ["/api/catalog"] => {get_catalog(request, &model.catalog_gz)},
-            (GET) ["/api/download/{slug}", slug: String] => {get_download(&model, &slug)},
-            (GET) ["/api/image/{id}", id: String] => {get_image(request, &id)},
-            (GET) ["/"] => {get_index(request, &model)},
-            _ => get_index(request, &model),
-        )
+        // Without this, a panic in a route would only show up as a bare 500 with no hint of
+        // which request caused it.
+        let started = Instant::now();
+        let response = match panic::catch_unwind(AssertUnwindSafe(|| handle(request, &shared))) {
+            Ok(response) => response,
+            Err(_) => {
+                error!("Request to {} panicked", request.raw_url());
+                get_internal_error(request)
+            }
+        };
+        // The time it took to start responding. Big downloads keep going long after this.
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        if tracing::enabled!(tracing::Level::INFO) {
+            let origin = request
+                .header("x-forwarded-for")
+                .filter(|_| behind_proxy)
+                .unwrap_or(&remote_addr);
+            let url = format!(
+                "{protocol}://{host}{path}",
+                protocol = if is_https_enabled { "https" } else { "http" },
+                host = request.header("host").unwrap_or(""),
+                path = request.raw_url()
+            );
+            info!(
+                origin,
+                status = response.status_code,
+                ms = elapsed_ms,
+                "{} {}",
+                request.method(),
+                url
+            );
+        }
+        count_in_flight(response, &handler_in_flight)
     };
 
-    if config.https {
-        let certificate = fs::read(&config.ssl_certificate).unwrap();
-        let private_key = fs::read(&config.ssl_private_key).unwrap();
-        println!(
-            "Grifter started on https://{}:{}",
-            config.address, config.https_port
-        );
-        Server::new_ssl(
-            (config.address.as_str(), config.https_port),
-            handler,
-            certificate,
-            private_key,
+    let (scheme, port) = if https {
+        ("https", config.https_port)
+    } else {
+        ("http", config.http_port)
+    };
+    let (certificate, private_key) = if https {
+        (
+            fs::read(&config.ssl_certificate)?,
+            fs::read(&config.ssl_private_key)?,
         )
-        .expect("Failed to start server")
-        .pool_size(8 * num_cpus::get())
-        .run()
     } else {
-        println!(
-            "Grifter started on http://{}:{}",
-            config.address, config.http_port
-        );
-        Server::new((config.address.as_str(), config.http_port), handler)
-            .expect("Failed to start server")
-            .pool_size(8 * num_cpus::get())
-            .run();
+        (Vec::new(), Vec::new())
+    };
+    let handler = Arc::new(handler);
+    let bind = |address: IpAddr, port: u16| {
+        let handler = Arc::clone(&handler);
+        let handler = move |request: &Request| handler(request);
+        let server = if https {
+            // A certificate or key that exists but is malformed is only caught here.
+            Server::new_ssl(
+                (address, port),
+                handler,
+                certificate.clone(),
+                private_key.clone(),
+            )
+        } else {
+            Server::new((address, port), handler)
+        };
+        // Like a port that's already taken, or an address that isn't on this machine.
+        server.map_err(|err| io::Error::new(io::ErrorKind::Other, err))
     };
 
-    // Will only reach here if the server crashes.
-    panic!("The server closed unexpectedly");
-}
-
-fn get_index(request: &Request, model: &Model) -> Response {
-    let index = match model.assets_gz.get("/index.html") {
-        Some(index) => index,
-        None => return Response::empty_404(),
+    let mut ipv4_thread = None;
+    let server = match (&config.unix_socket, peers) {
+        (Some(path), Some(peers)) => {
+            // The OS picks a free port for the relay to connect to.
+            let server = bind(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)?;
+            let upstream = server.server_addr();
+            relay::serve_unix(path, config.unix_socket_mode, upstream, idle_timeout, peers)?;
+            info!("Grifter started on {}", path.display());
+            shutdown::listen()?;
+            server
+        }
+        (None, Some(peers)) => {
+            // The server library can't time out idle connections itself, so they're accepted
+            // by the relay, which can. https is still done by the server, so it's relayed as is.
+            let server = bind(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)?;
+            let upstream = server.server_addr();
+            let listener = TcpListener::bind((config.address, port))?;
+            info!("Grifter started on {}://{}", scheme, listener.local_addr()?);
+            shutdown::listen()?;
+            // See the same fallback below.
+            if config.address == IpAddr::V6(Ipv6Addr::UNSPECIFIED) {
+                if let Ok(ipv4_listener) = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)) {
+                    info!(
+                        "Grifter started on {}://{}",
+                        scheme,
+                        ipv4_listener.local_addr()?
+                    );
+                    relay::serve_tcp(ipv4_listener, upstream, idle_timeout, Arc::clone(&peers))?;
+                }
+            }
+            relay::serve_tcp(listener, upstream, idle_timeout, peers)?;
+            server
+        }
+        (_, None) => {
+            let server = bind(config.address, port)?;
+            info!("Grifter started on {}://{}", scheme, server.server_addr());
+            shutdown::listen()?;
+            // Whether "::" also takes IPv4 connections is up to the OS. It does by default on
+            // Linux, but not on Windows or the BSDs, so those get a separate IPv4 server. Binding
+            // it fails when "::" already covers IPv4, which is fine.
+            if config.address == IpAddr::V6(Ipv6Addr::UNSPECIFIED) {
+                if let Ok(ipv4_server) = bind(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port) {
+                    info!(
+                        "Grifter started on {}://{}",
+                        scheme,
+                        ipv4_server.server_addr()
+                    );
+                    ipv4_thread = Some(std::thread::spawn(move || {
+                        serve_until_shutdown(ipv4_server.pool_size(pool_size))
+                    }));
+                }
+            }
+            server
+        }
     };
+    serve_until_shutdown(server.pool_size(pool_size));
+    if let Some(ipv4_thread) = ipv4_thread {
+        let _ = ipv4_thread.join();
+    }
+    if let Some(path) = &config.unix_socket {
+        let _ = fs::remove_file(path);
+    }
 
-    let csp = [
-        "default-src 'none'",
-        "font-src https://fonts.gstatic.com",
-        "img-src 'self' https://i.ytimg.com",
-        "connect-src 'self'",
-        "script-src 'self'",
-        "style-src 'self' 'unsafe-inline'",
-        "frame-ancestors 'none'",
-        "frame-src https://www.youtube-nocookie.com/",
-        "base-uri 'none'",
-        "require-trusted-types-for 'script'",
-        "form-action 'none'",
-    ];
-    Response::from_data(index.mime, index.bytes.clone())
-        .with_unique_header("content-encoding", "gzip")
-        .with_unique_header("content-security-policy", csp.join("; "))
-        .with_unique_header("referrer-policy", "no-referrer")
-        .with_unique_header("x-content-type-options", "nosniff")
-        .with_unique_header("x-frame-options", "deny")
-        .with_unique_header("x-xss-protection", "1; mode=block")
-        .with_etag(request, index.hash.clone())
-        .with_public_cache(60)
+    // New connections aren't accepted anymore, but the ones already going are left to finish so
+    // nobody's download gets cut off. Closing the prefetch channel lets the prefetcher get
+    // through the images that are already queued, then stop.
+    info!("Shutting down. Press Ctrl-C again to stop right away.");
+    wait_until("downloads", || in_flight.load(Ordering::SeqCst) == 0);
+    lock(&on_shutdown.prefetch).take();
+    wait_until("images", || prefetch_pool.is_finished());
+    if save_cache_usage {
+        cache_usage.save();
+    }
+    info!("Stopped.");
+    Ok(())
 }
 
-fn get_asset(request: &Request, asset: &GzippedAsset) -> Response {
-    // Asset caching is implemented with ETagging because the index isn't dynamically generated
-    // so there's no way to embed the hash. I don't actually think it's worth the effort atm.
-    // ETagging is just fine.
+// The server stops listening once it's dropped, on the way out of here.
+fn serve_until_shutdown<F>(server: Server<F>)
+where
+    F: Send + Sync + 'static + Fn(&Request) -> Response,
+{
+    while !shutdown::is_requested() {
+        server.poll_timeout(std::time::Duration::from_millis(200));
+    }
+}
 
-    Response::from_data(asset.mime, asset.bytes.clone())
-        .with_unique_header("content-encoding", "gzip")
-        .with_etag(request, asset.hash.clone())
-        .with_public_cache(60 * 60 * 24)
+// Pressing Ctrl-C again ends the process instead of waiting any longer.
+fn wait_until(what: &str, is_done: impl Fn() -> bool) {
+    if is_done() {
+        return;
+    }
+    info!("Waiting for {} to finish.", what);
+    while !is_done() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
 }
 
-fn get_download(model: &Model, slug: &str) -> Response {
-    let game = match model.catalog.games.iter().find(|game| game.slug == slug) {
-        Some(game) => game,
-        None => {
-            println!("Download failed: slug doesn't exist {:?}", slug);
-            return Response::empty_404();
-        }
+fn count_in_flight(mut response: Response, in_flight: &Arc<AtomicUsize>) -> Response {
+    in_flight.fetch_add(1, Ordering::SeqCst);
+    let (reader, size) = response.data.into_reader_and_size();
+    let reader = InFlight {
+        reader,
+        count: Arc::clone(in_flight),
     };
-
-    let file = match File::open(&game.path) {
-        Ok(file) => file,
-        Err(_) => {
-            println!("Download failed: file doesn't exist {:?}", game.path);
-            return Response::empty_404();
-        }
+    response.data = match size {
+        Some(size) => ResponseBody::from_reader_and_size(reader, size),
+        None => ResponseBody::from_reader(reader),
     };
+    response
+}
 
-    let save_as = game
-        .path
-        .file_name()
-        .and_then(|f| f.to_str())
-        .unwrap_or(slug);
-    Response::from_file("application/octet-stream", file).with_unique_header(
-        "content-disposition",
-        format!("attachment; filename=\"{}\"", save_as),
-    )
+// Counted until it's dropped, which is when the response is done being sent, or the client went
+// away partway through.
+struct InFlight {
+    reader: Box<dyn Read + Send>,
+    count: Arc<AtomicUsize>,
 }
 
-fn get_catalog(request: &Request, catalog: &GzippedAsset) -> Response {
-    Response::from_data(extension_to_mime("json"), catalog.bytes.clone())
-        .with_unique_header("content-encoding", "gzip")
-        .with_etag(request, catalog.hash.clone())
-        .with_public_cache(60)
+impl Read for InFlight {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
 }
 
-enum ImageSize {
-    Thumbnail,
-    Original,
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
-fn get_image(request: &Request, image_id: &str) -> Response {
-    let size = match request.get_param("size").as_deref() {
-        Some("Thumbnail") => ImageSize::Thumbnail,
-        Some("Original") => ImageSize::Original,
-        _ => return Response::empty_404(),
-    };
+fn handle(request: &Request, shared: &Shared) -> Response {
+    let model = &*shared.model();
+    if let Some(asset) = model.assets_gz.get(request.raw_url()) {
+        return get_asset(request, asset);
+    }
 
-    let path = match size {
-        ImageSize::Thumbnail => image_cache(image_id).join("thumbnail.jpeg"),
-        ImageSize::Original => image_cache(image_id).join("original.jpeg"),
-    };
+    if let Some(response) = check_method(request) {
+        return response;
+    }
+    // The server leaves the body out of responses to HEAD requests, so they're otherwise
+    // answered like a GET.
+    if request.method() == "HEAD" {
+        return route(&as_get(request), shared, model);
+    }
+    route(request, shared, model)
+}
 
-    match std::fs::File::open(path) {
-        Ok(image) => Response::from_file("image/jpeg", image)
-            .with_unique_header("cache-control", "max-age=10368000, immutable"), // 10368000 seconds = 120 days
-        Err(_) => Response::empty_404(),
+fn route(request: &Request, shared: &Shared, model: &Model) -> Response {
+    router!(request,
+        (GET) ["/api/catalog"] => {
+            match request.get_param("view").as_deref() {
+                Some("compact") => get_catalog(request, &model.compact_catalog_gz),
+                _ => match (&shared.store, &model.catalog_gz) {
+                    (Some(store), _) => get_stored_catalog(request, store, &model.catalog),
+                    (None, Some(catalog)) => get_catalog(request, catalog),
+                    (None, None) => Response::empty_404(),
+                },
+            }
+        },
+        (GET) ["/api/catalog.ndjson"] => {get_catalog_ndjson(shared.model())},
+        (GET) ["/api/catalog.csv"] => {get_catalog_csv(request, &model.catalog_csv_gz)},
+        (GET) ["/api/search"] => {get_search(request, shared, model)},
+        (GET) ["/browse"] => {get_browse(request, &model.browse_gz)},
+        (GET) ["/manifest.webmanifest"] => {get_asset(request, &model.manifest_gz)},
+        (GET) ["/api/download/{slug}", slug: String] => {get_download(request, model, &slug)},
+        (GET) ["/api/download-all/{slug}", slug: String] => {get_download_all(model, &slug)},
+        (GET) ["/api/checksum/{slug}", slug: String] => {get_checksum(model, &slug)},
+        (GET) ["/api/game/{slug}", slug: String] => {get_game(request, shared, model, &slug)},
+        (GET) ["/api/download/{slug}/extras/{index}", slug: String, index: usize] => {
+            get_extra(request, model, &slug, index)
+        },
+        (GET) ["/api/download/{slug}/dlc/{index}", slug: String, index: usize] => {
+            get_dlc(request, model, &slug, index)
+        },
+        (GET) ["/api/image/{id}", id: String] => {get_image(request, shared, &id)},
+        (POST) ["/api/reload"] => {post_reload(request, shared)},
+        (GET) ["/api/warnings"] => {get_warnings(request, shared)},
+        (GET) ["/api/cache"] => {get_cache(request, shared)},
+        (GET) ["/api/health"] => {get_health(shared, model)},
+        (GET) ["/api/ready"] => {get_ready(shared)},
+        (GET) ["/healthz"] => {Response::text("ok").with_no_cache()},
+        (GET) ["/readyz"] => {get_ready(shared)},
+        (GET) ["/games/{slug}", slug: String] => {get_game_page(request, model, &slug)},
+        (GET) ["/"] => {get_index(request, model)},
+        _ => get_index(request, model),
+    )
+}
+
+// Answers OPTIONS, and requests to an API route with a method it doesn't take. Without this, a
+// request with the wrong method would fall through to the index.
+fn check_method(request: &Request) -> Option<Response> {
+    let allowed = allowed_methods(&request.url())?;
+    if request.method() == "OPTIONS" {
+        return Some(Response::empty_204().with_unique_header("allow", allowed));
+    }
+    if !allowed.split(", ").any(|method| method == request.method()) {
+        return Some(
+            Response::text("method not allowed")
+                .with_status_code(405)
+                .with_unique_header("allow", allowed),
+        );
     }
+    None
 }
 
-fn image_cache(image_id: &str) -> PathBuf {
-    let cache_root = Path::new("./cache");
-    if let Err(e) = fs::create_dir(cache_root) {
-        match e.kind() {
-            io::ErrorKind::AlreadyExists => { /* this is fine */ }
-            _ => panic!("failed to create cache directory"),
-        }
+fn as_get(request: &Request) -> Request {
+    let headers = request
+        .headers()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let url = request.raw_url().to_string();
+    if request.is_secure() {
+        Request::fake_https_from(*request.remote_addr(), "GET", url, headers, Vec::new())
+    } else {
+        Request::fake_http_from(*request.remote_addr(), "GET", url, headers, Vec::new())
     }
-    let image_dir = cache_root.join(image_id);
-    if let Err(e) = fs::create_dir(&image_dir) {
-        match e.kind() {
-            io::ErrorKind::AlreadyExists => { /* this is fine */ }
-            _ => panic!("failed to create cache directory"),
+}
+
+// The methods each API route answers to, for the "allow" header.
+fn allowed_methods(url: &str) -> Option<&'static str> {
+    match url {
+        "/api/reload" => Some("POST, OPTIONS"),
+        "/api/catalog"
+        | "/api/catalog.ndjson"
+        | "/api/catalog.csv"
+        | "/api/search"
+        | "/api/warnings"
+        | "/api/cache"
+        | "/api/health"
+        | "/api/ready"
+        | "/healthz"
+        | "/readyz" => Some("GET, HEAD, OPTIONS"),
+        _ if url.starts_with("/api/download/")
+            || url.starts_with("/api/download-all/")
+            || url.starts_with("/api/checksum/")
+            || url.starts_with("/api/game/")
+            || url.starts_with("/api/image/") =>
+        {
+            Some("GET, HEAD, OPTIONS")
         }
+        _ => None,
     }
+}
+
+// Waits for the OS to say something changed instead of polling, so nothing is read until there's
+// a reason to. Notifications don't come through for changes made on another machine to a network
+// drive, which is what `reindex_interval_hours` and /api/reload are for.
+fn watch_for_changes(shared: &Shared, paths: Vec<PathBuf>) {
+    // Long enough for an editor's save, or a few games dropped into the root at once, to be
+    // done. A game that's still copying keeps changing, so it's waited on too.
+    const SETTLE_TIME: std::time::Duration = std::time::Duration::from_secs(5);
 
-    image_dir
+    let (sender, receiver) = unbounded();
+    let mut debouncer = match new_debouncer(SETTLE_TIME, sender) {
+        Ok(debouncer) => debouncer,
+        Err(err) => {
+            warn!("Couldn't watch for changes: {}", err);
+            return;
+        }
+    };
+    let mut watched = WatchedPaths::default();
+    watched.update(debouncer.watcher(), paths);
+    for events in receiver {
+        let events = match events {
+            Ok(events) => events,
+            Err(err) => {
+                warn!("Couldn't watch for changes: {}", err);
+                continue;
+            }
+        };
+        let changed = events
+            .iter()
+            .any(|event| event.kind == DebouncedEventKind::Any && watched.contains(&event.path));
+        if !changed {
+            continue;
+        }
+        info!("Found changes to the config or the games, re-indexing.");
+        // Failures are already logged, and the next change gets another shot. The config itself
+        // is still watched, so fixing it picks up whatever else it says to watch.
+        if let Ok(summary) = reload(shared) {
+            watched.update(debouncer.watcher(), summary.watched);
+        }
+    }
 }
 
-struct JobThread {
-    is_busy: bool,
-    sender: Sender<String>,
+// Everything an index depends on: the config, the games file, each root, and each game's own path
+// (which might be nested further down in a root).
+fn watched_paths(config: &Config) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(config::FILENAME)];
+    paths.extend(config.games_file.iter().cloned());
+    paths.extend(config.root.iter().cloned());
+    paths.extend(config.games.iter().map(|game| config.resolve(&game.path)));
+    paths
 }
 
-pub fn image_prefetch_pool(thread_count: usize, jobs: Receiver<String>) {
-    let mut threads = Vec::with_capacity(thread_count);
-    let (on_complete, job_finished) = bounded(thread_count);
-    for thread in 0..thread_count {
-        let (s, r) = bounded(1);
-        let on_complete = on_complete.clone();
-        std::thread::spawn(move || image_prefetch_worker(thread, r, on_complete));
-        threads.push(JobThread {
-            is_busy: false,
-            sender: s,
-        });
-    }
+#[derive(Default)]
+struct WatchedPaths {
+    paths: Vec<PathBuf>,
+    folders: HashSet<PathBuf>,
+}
 
-    for job in jobs.into_iter() {
-        let free_thread = threads.iter_mut().find(|thread| !thread.is_busy);
-        match free_thread {
-            Some(thread) => {
-                thread.is_busy = true;
-                thread.sender.send(job).unwrap();
-            }
-            None => {
-                // now we wait for a thread
-                let thread_index = job_finished.recv().unwrap();
-                threads[thread_index].sender.send(job).unwrap(); // no rest, get back to work lmao
+impl WatchedPaths {
+    // Events come back with the path of the folder that was watched, so everything is made
+    // absolute to compare against them.
+    fn update(&mut self, watcher: &mut dyn Watcher, paths: Vec<PathBuf>) {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        self.paths = paths.iter().map(|path| current_dir.join(path)).collect();
+        // Each path's folder is watched instead of the path itself, since editors tend to save by
+        // replacing the file. Folders (roots, and games that are folders) are watched themselves
+        // too, for what's added to or removed from them. Only the top of each is watched because
+        // the roots can be huge.
+        let mut folders = HashSet::new();
+        for path in self.paths.iter() {
+            folders.extend(path.parent().map(Path::to_path_buf));
+            if path.is_dir() {
+                folders.insert(path.clone());
             }
         }
+        for folder in self.folders.difference(&folders) {
+            let _ = watcher.unwatch(folder);
+        }
+        // A folder that doesn't exist yet can't be watched, but its root or the config is, so
+        // it's picked up on the next re-index after it shows up.
+        for folder in folders.difference(&self.folders) {
+            let _ = watcher.watch(folder, RecursiveMode::NonRecursive);
+        }
+        self.folders = folders;
+    }
+
+    fn contains(&self, changed: &Path) -> bool {
+        self.paths.iter().any(|path| changed.starts_with(path))
     }
 }
 
-fn image_prefetch_worker(thread: usize, receiver: Receiver<String>, on_complete: Sender<usize>) {
-    for image_id in receiver.into_iter() {
-        let cache = image_cache(&image_id);
-        let original_path = cache.join("original.jpeg");
-        let original = match image::open(&original_path) {
-            Ok(original) => original,
-            Err(_) => {
-                let image = igdb::get_image(&image_id).unwrap();
-                let original =
-                    image::load_from_memory_with_format(&image.bytes[..], image.format).unwrap();
-                original
-                    .save_with_format(&original_path, image::ImageFormat::Jpeg)
-                    .unwrap();
-                original
-            }
-        };
+#[derive(Serialize)]
+struct ReloadSummary {
+    added: Vec<String>,
+    removed: Vec<String>,
+    warnings: Vec<String>,
+    // What the new config says to watch for changes. See `watch_for_changes`.
+    #[serde(skip)]
+    watched: Vec<PathBuf>,
+}
 
-        let thumbnail_path = cache.join("thumbnail.jpeg");
-        let _thumbnail = match image::open(&thumbnail_path) {
-            Ok(thumbnail) => thumbnail,
-            Err(_) => {
-                let (tw, th) = max_dimensions(original.dimensions(), (None, Some(200)));
-                let thumbnail = original.thumbnail(tw, th);
-                thumbnail
-                    .save_with_format(&thumbnail_path, image::ImageFormat::Jpeg)
-                    .unwrap();
-                thumbnail
-            }
-        };
-        println!("Loaded: {}", image_id);
-        on_complete.send(thread).unwrap();
+fn post_reload(request: &Request, shared: &Shared) -> Response {
+    if let Some(response) = reject_unauthorized(request, shared) {
+        return response;
+    }
+    match reload(shared) {
+        Ok(summary) => Response::json(&summary),
+        Err(message) => {
+            Response::json(&serde_json::json!({ "error": message })).with_status_code(500)
+        }
     }
 }
 
-fn max_dimensions(dimensions: (u32, u32), max: (Option<u32>, Option<u32>)) -> (u32, u32) {
-    let (mut width, mut height) = dimensions;
-    let (max_width, max_height) = max;
-    if let Some(max_width) = max_width {
-        width = u32::min(width, max_width);
+// Re-reads the config, re-indexes every game, and swaps in the new catalog. Anything that
+// rebuilds the model holds the metadata lock, so rebuilds can't race each other.
+fn reload(shared: &Shared) -> Result<ReloadSummary, String> {
+    let mut metadata = lock(&shared.metadata);
+    let reload_failed = |message: String| {
+        error!("Reload failed: {}", message);
+        Err(message)
+    };
+
+    let config_text = match fs::read_to_string(config::FILENAME) {
+        Ok(text) => text,
+        Err(err) => return reload_failed(format!("couldn't read {}: {}", config::FILENAME, err)),
+    };
+    let (config, config_warnings) = match Config::from_str(&config_text) {
+        Ok(config) => config,
+        Err(err) => return reload_failed(format!("bad config: {}", err)),
+    };
+    let (games, game_warnings) = match game::games_from_config(&config, metadata.as_mut()) {
+        Ok(games) => games,
+        Err(err) => return reload_failed(format!("indexing failed: {}", err)),
+    };
+    let warnings = config_warnings
+        .iter()
+        .map(IndexWarning::from)
+        .chain(game_warnings.iter().map(IndexWarning::from))
+        .collect();
+    let model = build_model(&config, metadata.as_mut(), games, warnings, &shared.store);
+
+    // Games that failed to fetch would look like they don't use their images anymore.
+    if config.remove_unused_images && !game::is_incomplete(&game_warnings) {
+        remove_unused_images(&model.catalog.games);
     }
-    if let Some(max_height) = max_height {
-        height = u32::min(height, max_height);
+    // Give images that failed before another shot.
+    lock(&shared.failed_images).clear();
+    if let Some(prefetch) = shared.prefetch() {
+        queue_images(&prefetch, &model.catalog.games);
     }
-    (width, height)
+    queue_checksums(&shared.checksums, &model.catalog.games);
+    update_store(&shared.store, &model.catalog.games);
+    let new_model = Arc::new(model);
+    let old_model = std::mem::replace(
+        &mut *shared.model.write().unwrap_or_else(PoisonError::into_inner),
+        Arc::clone(&new_model),
+    );
+
+    let slugs = |model: &Model| -> Vec<String> {
+        model.catalog.games.iter().map(|g| g.slug.clone()).collect()
+    };
+    let (old_slugs, new_slugs) = (slugs(&old_model), slugs(&new_model));
+    let summary = ReloadSummary {
+        added: new_slugs
+            .iter()
+            .filter(|slug| !old_slugs.contains(slug))
+            .cloned()
+            .collect(),
+        removed: old_slugs
+            .iter()
+            .filter(|slug| !new_slugs.contains(slug))
+            .cloned()
+            .collect(),
+        warnings: config_warnings
+            .iter()
+            .map(ToString::to_string)
+            .chain(game_warnings.iter().map(ToString::to_string))
+            .collect(),
+        watched: watched_paths(&config),
+    };
+    info!(
+        "Reloaded {} games ({} added, {} removed).",
+        new_slugs.len(),
+        summary.added.len(),
+        summary.removed.len()
+    );
+    Ok(summary)
 }
 
-fn encoded_hash(bytes: &[u8]) -> String {
-    use blake2::digest::{Update, VariableOutput};
-    use blake2::VarBlake2b;
+// Ready once enough of the images queued at startup have been through the prefetcher, so a load
+// balancer doesn't send people to a server that's still downloading every cover.
+fn get_health(shared: &Shared, model: &Model) -> Response {
+    Response::json(&serde_json::json!({
+        "status": "ok",
+        "games": model.catalog.games.len(),
+        "uptime_seconds": shared.started.elapsed().as_secs(),
+    }))
+    .with_no_cache()
+}
 
-    let mut hash = String::new();
-    let mut hasher = VarBlake2b::new(10).unwrap();
-    hasher.update(bytes);
-    hasher.finalize_variable(|hash_bytes| {
-        let config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-        hash = base64::encode_config(hash_bytes, config);
-    });
-    hash
+// The server isn't started until the catalog is built and Twitch has let us in, so only the
+// prefetched images are left to wait on.
+fn get_ready(shared: &Shared) -> Response {
+    if !shared.is_ready.load(Ordering::Relaxed) {
+        // Anything still in the channel hasn't been picked up yet. Once the initial images are
+        // through, it stays ready even when a reload queues more.
+        let queued = shared.prefetch().map_or(0, |prefetch| prefetch.len());
+        let remaining = usize::min(queued, shared.initial_images);
+        let prefetched = match shared.initial_images {
+            0 => 1.0,
+            queued => (queued - remaining) as f64 / queued as f64,
+        };
+        if prefetched < shared.ready_after_prefetch {
+            return Response::text("not ready")
+                .with_status_code(503)
+                .with_no_cache();
+        }
+        shared.is_ready.store(true, Ordering::Relaxed);
+    }
+    Response::text("ok").with_no_cache()
 }
 
-pub fn gzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
-    use flate2::write::GzEncoder;
+// The admin endpoints don't exist unless there's an admin token configured.
+fn reject_unauthorized(request: &Request, shared: &Shared) -> Option<Response> {
+    match &shared.admin_token {
+        Some(token) if is_authorized(request, token) => None,
+        Some(_) => Some(Response::text("unauthorized").with_status_code(401)),
+        None => Some(Response::empty_404()),
+    }
+}
 
-    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+// Lists the problems found by the last index so they can be fixed without digging through the
+// server's output.
+fn get_warnings(request: &Request, shared: &Shared) -> Response {
+    if let Some(response) = reject_unauthorized(request, shared) {
+        return response;
+    }
+    Response::json(&shared.model().warnings).with_no_cache()
+}
+
+#[derive(Serialize)]
+struct CacheReport {
+    total_bytes: u64,
+    images: Vec<CachedImage>,
+}
+
+#[derive(Serialize)]
+struct CachedImage {
+    id: String,
+    bytes: u64, // Everything cached for the image: the original and its thumbnails.
+    // Missing for images cached before meta.json was written.
+    meta: Option<ImageMeta>,
+}
+
+// Lists what's in the image cache, biggest first. It's read from disk on every request, so it
+// doesn't go stale but isn't free either.
+fn get_cache(request: &Request, shared: &Shared) -> Response {
+    if let Some(response) = reject_unauthorized(request, shared) {
+        return response;
+    }
+    let image_dirs = fs::read_dir(CACHE_ROOT).into_iter().flatten();
+    let mut images: Vec<CachedImage> = image_dirs
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| {
+            let path = entry.path();
+            let bytes = dir_bytes(&path);
+            let meta = fs::read(path.join("meta.json"))
+                .ok()
+                .and_then(|meta| serde_json::from_slice(&meta).ok());
+            CachedImage {
+                id: entry.file_name().to_string_lossy().into_owned(),
+                bytes,
+                meta,
+            }
+        })
+        .collect();
+    images.sort_by_key(|image| Reverse(image.bytes));
+    let report = CacheReport {
+        total_bytes: images.iter().map(|image| image.bytes).sum(),
+        images,
+    };
+    Response::json(&report).with_no_cache()
+}
+
+// Load balancers and container runtimes don't have a password to send.
+fn is_health_check(url: &str) -> bool {
+    matches!(url, "/api/health" | "/api/ready" | "/healthz" | "/readyz")
+}
+
+// Checking a bcrypt hash is slow on purpose, too slow to do for every image on a page, so
+// credentials that have been checked once are remembered by a (fast) hash of them.
+struct BasicAuth {
+    users: Vec<config::User>,
+    verified: Mutex<HashSet<String>>,
+}
+
+impl BasicAuth {
+    fn allows(&self, request: &Request) -> bool {
+        let header = match request.header("authorization") {
+            Some(header) => header,
+            None => return false,
+        };
+        let fingerprint = encoded_hash(header.as_bytes());
+        if lock(&self.verified).contains(&fingerprint) {
+            return true;
+        }
+        let credentials = header
+            .strip_prefix("Basic ")
+            .and_then(|encoded| base64::decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let (username, password) = match credentials.as_deref().and_then(|c| c.split_once(':')) {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+        let is_valid = self.users.iter().any(|user| {
+            user.username == username
+                && bcrypt::verify(password, &user.password_hash).unwrap_or(false)
+        });
+        if is_valid {
+            lock(&self.verified).insert(fingerprint);
+        }
+        is_valid
+    }
+}
+
+// Checks for an "authorization: Bearer <token>" header matching the admin token.
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    let actual = request.header("authorization").unwrap_or("");
+    // Compare every byte so the response time doesn't leak how much of the token was right.
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn drop_failed_images(shared: &Shared, failures: Receiver<String>) {
+    for image_id in failures.iter() {
+        // Failures tend to come in bunches (like when IGDB is down), so collect the rest of
+        // the bunch before rebuilding the catalog.
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let _metadata = lock(&shared.metadata);
+        let failed_images = {
+            let mut failed_images = lock(&shared.failed_images);
+            failed_images.insert(image_id);
+            failed_images.extend(failures.try_iter());
+            failed_images.clone()
+        };
+
+        let mut model = Model::clone(&shared.model());
+        let mut changed = Vec::new();
+        for game in model.catalog.games.iter_mut() {
+            let screenshots = game.screenshots.len();
+            let cover_failed = game
+                .cover
+                .as_ref()
+                .map_or(false, |cover| failed_images.contains(&cover.id));
+            if cover_failed {
+                game.cover = None;
+            }
+            game.screenshots
+                .retain(|screenshot| !failed_images.contains(&screenshot.id));
+            if cover_failed || game.screenshots.len() != screenshots {
+                changed.push(game.clone());
+            }
+        }
+        save_changed_games(&shared.store, &mut model, &changed);
+        model.browse_gz = browse_gz(&model.catalog);
+        *shared.model.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(model);
+    }
+}
+
+// When the catalog database can't be updated, it keeps serving the games it had before.
+fn update_store(store: &Option<Store>, games: &[Game]) {
+    if let Some(store) = store {
+        if let Err(err) = store.replace(games) {
+            warn!("Failed to update the catalog database: {}", err);
+        }
+    }
+}
+
+// For games that changed without a re-index, like when their checksums are filled in. They're
+// saved to the catalog database when there is one, and otherwise compressed again along with the
+// whole catalog. The model is a clone that would still share the game pages built from the old
+// games, like one with a cover that failed to cache, so those start over.
+fn save_changed_games(store: &Option<Store>, model: &mut Model, changed: &[Game]) {
+    model.game_pages = Default::default();
+    match store {
+        Some(store) => {
+            if let Err(err) = store.update(changed) {
+                warn!("Failed to update the catalog database: {}", err);
+            }
+        }
+        None => {
+            for game in changed {
+                model.games_gz.insert(game.slug.clone(), game_gz(game));
+            }
+            model.catalog_gz = Some(catalog_gz(&model.catalog));
+        }
+    }
+    model.compact_catalog_gz = compact_catalog_gz(&model.catalog);
+}
+
+fn queue_checksums(sender: &Sender<PathBuf>, games: &[Game]) {
+    for game in games {
+        // Folders are zipped on the fly, so there's no one file to hash.
+        if game.available && game.blake2b.is_none() && game.path.is_file() {
+            sender.send(game.path.clone()).unwrap();
+        }
+    }
+}
+
+// Hashes the games in the background, adding their checksums to the catalog in batches. Every
+// change means compressing the whole catalog again, so checksums are held back until the queue
+// runs out or a batch has been going for a while, whichever comes first.
+fn fill_checksums(shared: &Shared, jobs: Receiver<PathBuf>) {
+    const BATCH_TIME: std::time::Duration = std::time::Duration::from_secs(10);
+
+    let mut checksums = Checksums::load();
+    for first in jobs.iter() {
+        let started = Instant::now();
+        let mut hashed = Vec::new();
+        let mut next = Some(first);
+        while let Some(path) = next {
+            match checksums.compute(&path) {
+                Ok(blake2b) => hashed.push((path, blake2b)),
+                Err(err) => warn!("Failed to hash {:?}: {}", path, err),
+            }
+            next = if started.elapsed() < BATCH_TIME {
+                jobs.try_recv().ok()
+            } else {
+                None
+            };
+        }
+        if hashed.is_empty() {
+            continue;
+        }
+        if let Err(err) = checksums.save() {
+            warn!("Failed to save checksums: {}", err);
+        }
+
+        let _metadata = lock(&shared.metadata);
+        let mut model = Model::clone(&shared.model());
+        let mut changed = Vec::new();
+        for game in model.catalog.games.iter_mut() {
+            if let Some((_, blake2b)) = hashed.iter().find(|(path, _)| *path == game.path) {
+                game.blake2b = Some(blake2b.clone());
+                changed.push(game.clone());
+            }
+        }
+        save_changed_games(&shared.store, &mut model, &changed);
+        *shared.model.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(model);
+    }
+}
+
+// A panic while holding one of these locks doesn't leave the data inside in a bad state, so
+// there's no reason to let poisoning take down every request after it.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Returns how many images were queued.
+fn queue_images(sender: &Sender<String>, games: &[Game]) -> usize {
+    // Different games can share images (like a game listed twice, or a compilation and its
+    // parts), so only queue each image once.
+    let mut queued = HashSet::new();
+    for game in games {
+        let images = game.screenshots.iter().chain(game.cover.as_ref());
+        // Local images don't go in the cache, they're served from where they are.
+        for image in images.filter(|image| image.source.is_none()) {
+            if queued.insert(image.id.as_str()) {
+                sender.send(image.id.clone()).unwrap();
+            }
+        }
+    }
+    queued.len()
+}
+
+fn get_internal_error(request: &Request) -> Response {
+    let response = if request.raw_url().starts_with("/api/") {
+        Response::from_data(
+            extension_to_mime("json"),
+            r#"{"error":"internal server error"}"#,
+        )
+    } else {
+        Response::html(
+            "<!DOCTYPE html><title>Grifter</title>\
+            <h1>Something went wrong</h1>\
+            <p>The server ran into a problem handling this page. Try again in a bit.</p>",
+        )
+    };
+    response.with_status_code(500).with_no_cache()
+}
+
+fn get_index(request: &Request, model: &Model) -> Response {
+    let index = match model.assets_gz.get("/index.html") {
+        Some(index) => index,
+        None => return Response::empty_404(),
+    };
+    index_response(request, index)
+}
+
+// The scheme and host the browser used to reach the server. Behind a reverse proxy, that's
+// whatever the proxy says it was.
+fn origin(request: &Request, behind_proxy: bool) -> String {
+    let forwarded = |name| {
+        let value = request.header(name).filter(|_| behind_proxy)?;
+        // Proxies in a chain each append their own value, the first one is the browser's.
+        value.split(',').next().map(str::trim)
+    };
+    let scheme = match forwarded("x-forwarded-proto") {
+        Some(scheme) => scheme,
+        None if request.is_secure() => "https",
+        None => "http",
+    };
+    let host = forwarded("x-forwarded-host")
+        .or_else(|| request.header("host"))
+        .unwrap_or("localhost");
+    format!("{}://{}", scheme, host)
+}
+
+// The IP address of whoever sent the request. Behind a proxy that's the first address in
+// X-Forwarded-For, since the connection itself comes from the proxy.
+fn client_ip(request: &Request, peer: IpAddr, behind_proxy: bool) -> Option<IpAddr> {
+    if !behind_proxy {
+        return Some(peer);
+    }
+    let forwarded_for = request.header("x-forwarded-for")?;
+    forwarded_for.split(',').next()?.trim().parse().ok()
+}
+
+// The index with Open Graph tags for the game added, so shared links get a rich preview. Pages
+// are kept for the next visit, unless it comes in through a different origin.
+fn get_game_page(request: &Request, model: &Model, slug: &str) -> Response {
+    let index = model.assets_gz.get("/index.html");
+    let game = find_game(&model.catalog.games, slug).filter(|game| !game.hidden);
+    let (index, game) = match (index, game) {
+        (Some(index), Some(game)) => (index, game),
+        _ => return get_index(request, model),
+    };
+
+    let origin = origin(request, model.behind_proxy);
+    let cached = lock(&model.game_pages)
+        .get(&game.slug)
+        .filter(|(page_origin, _)| *page_origin == origin)
+        .map(|(_, page)| Arc::clone(page));
+    let page = match cached {
+        Some(page) => page,
+        None => {
+            let page = Arc::new(game_page(index, game, &origin));
+            lock(&model.game_pages).insert(game.slug.clone(), (origin, Arc::clone(&page)));
+            page
+        }
+    };
+    index_response(request, &page)
+}
+
+fn game_page(index: &CompressedAsset, game: &Game, origin: &str) -> CompressedAsset {
+    let url = format!("{}/games/{}", origin, game.slug);
+    let mut tags = vec![
+        format!("<link rel=\"canonical\" href=\"{}\" />", escape_html(&url)),
+        "<meta property=\"og:type\" content=\"website\" />".to_string(),
+        format!(
+            "<meta property=\"og:url\" content=\"{}\" />",
+            escape_html(&url)
+        ),
+        format!(
+            "<meta property=\"og:title\" content=\"{}\" />",
+            escape_html(&game.name)
+        ),
+    ];
+    if let Some(summary) = &game.summary {
+        tags.push(format!(
+            "<meta property=\"og:description\" content=\"{}\" />",
+            escape_html(summary)
+        ));
+    }
+    if let Some(cover) = &game.cover {
+        let image = format!("{}/api/image/{}?size=Original", origin, cover.id);
+        tags.push(format!(
+            "<meta property=\"og:image\" content=\"{}\" />",
+            escape_html(&image)
+        ));
+    }
+
+    let head = format!("    {}\n  </head>", tags.join("\n    "));
+    let page = String::from_utf8_lossy(&index.uncompressed).replacen("  </head>", &head, 1);
+    compressed_asset(index.mime, page.as_bytes())
+}
+
+fn index_response(request: &Request, index: &CompressedAsset) -> Response {
+    let csp = [
+        "default-src 'none'",
+        "font-src https://fonts.gstatic.com",
+        "img-src 'self' https://i.ytimg.com",
+        "connect-src 'self'",
+        "script-src 'self'",
+        "style-src 'self' 'unsafe-inline'",
+        "frame-ancestors 'none'",
+        "frame-src https://www.youtube-nocookie.com/",
+        "base-uri 'none'",
+        "require-trusted-types-for 'script'",
+        "form-action 'none'",
+        "manifest-src 'self'",
+    ];
+    compressed_response(request, index)
+        .with_unique_header("content-security-policy", csp.join("; "))
+        .with_unique_header("referrer-policy", "no-referrer")
+        .with_unique_header("x-content-type-options", "nosniff")
+        .with_unique_header("x-frame-options", "deny")
+        .with_unique_header("x-xss-protection", "1; mode=block")
+        .with_public_cache(60)
+}
+
+fn get_asset(request: &Request, asset: &CompressedAsset) -> Response {
+    // Asset caching is implemented with ETagging because the index isn't dynamically generated
+    // so there's no way to embed the hash. I don't actually think it's worth the effort atm.
+    // ETagging is just fine.
+
+    compressed_response(request, asset).with_public_cache(60 * 60 * 24)
+}
+
+// Games can be looked up by slug, or by IGDB id which stays the same even if the slug changes.
+// Games that aren't on IGDB have an id of 0, so they can only be found by slug.
+fn find_game<'a>(games: &'a [Game], slug_or_id: &str) -> Option<&'a Game> {
+    games
+        .iter()
+        .find(|game| game.slug == slug_or_id || (game.id != 0 && game.id.to_string() == slug_or_id))
+}
+
+fn get_download(request: &Request, model: &Model, slug: &str) -> Response {
+    match find_game(&model.catalog.games, slug) {
+        Some(game) if game.path.is_dir() => {
+            zip_download(zip_entries(&game.path), &format!("{}.zip", game.slug))
+        }
+        Some(game) => download(request, &game.path, game.size_bytes, slug),
+        None => {
+            warn!("Download failed: slug doesn't exist {:?}", slug);
+            Response::empty_404()
+        }
+    }
+}
+
+fn get_extra(request: &Request, model: &Model, slug: &str, index: usize) -> Response {
+    let game = find_game(&model.catalog.games, slug);
+    match game.and_then(|game| game.extras.get(index)) {
+        Some(extra) if extra.path.is_dir() => {
+            zip_download(zip_entries(&extra.path), &format!("{}.zip", extra.label))
+        }
+        Some(extra) => download(request, &extra.path, extra.size_bytes, &extra.label),
+        None => {
+            warn!("Download failed: no extra {} for {:?}", index, slug);
+            Response::empty_404()
+        }
+    }
+}
+
+fn get_dlc(request: &Request, model: &Model, slug: &str, index: usize) -> Response {
+    let game = find_game(&model.catalog.games, slug);
+    match game.and_then(|game| game.dlc.get(index)) {
+        Some(dlc) if dlc.path.is_dir() => {
+            zip_download(zip_entries(&dlc.path), &format!("{}.zip", dlc.label))
+        }
+        Some(dlc) => download(request, &dlc.path, dlc.size_bytes, &dlc.label),
+        None => {
+            warn!("Download failed: no DLC {} for {:?}", index, slug);
+            Response::empty_404()
+        }
+    }
+}
+
+fn get_checksum(model: &Model, slug: &str) -> Response {
+    match find_game(&model.catalog.games, slug) {
+        Some(Game {
+            blake2b: Some(blake2b),
+            ..
+        }) => Response::text(blake2b.as_str()),
+        Some(_) => Response::text("The checksum hasn't been worked out yet.")
+            .with_status_code(503)
+            .with_unique_header("retry-after", "60"),
+        None => Response::empty_404(),
+    }
+}
+
+// The game and all of its extras and DLC in one zip, made while it's being downloaded.
+fn get_download_all(model: &Model, slug: &str) -> Response {
+    let game = match find_game(&model.catalog.games, slug) {
+        Some(game) => game,
+        None => {
+            warn!("Download failed: slug doesn't exist {:?}", slug);
+            return Response::empty_404();
+        }
+    };
+    let files = std::iter::once(&game.path)
+        .chain(game.extras.iter().map(|extra| &extra.path))
+        .chain(game.dlc.iter().map(|dlc| &dlc.path))
+        .flat_map(|path| zip_entries(path))
+        .collect();
+    zip_download(files, &format!("{}-complete.zip", game.slug))
+}
+
+// What goes in a zip for `path`: the file itself, or everything inside of it if it's a folder,
+// under the folder's name. Paths in zips are always separated by forward slashes.
+fn zip_entries(path: &Path) -> Vec<(String, PathBuf)> {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => {
+            warn!(
+                "Zip warning: skipped {:?}, it doesn't have a usable name",
+                path
+            );
+            return Vec::new();
+        }
+    };
+    if !path.is_dir() {
+        return vec![(name.to_string(), path.to_path_buf())];
+    }
+    game::files_in(path)
+        .into_iter()
+        .filter_map(|file| {
+            let relative = file.strip_prefix(path).ok()?;
+            let parts: Option<Vec<&str>> = relative.iter().map(|part| part.to_str()).collect();
+            match parts {
+                Some(parts) => Some((format!("{}/{}", name, parts.join("/")), file)),
+                None => {
+                    warn!(
+                        "Zip warning: skipped {:?}, it doesn't have a usable name",
+                        file
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Streams a zip of `files`, each a name in the zip and the file to put there. Files that can't
+// be read are left out rather than failing the whole download, since there's no way to report an
+// error once the response has started.
+fn zip_download(files: Vec<(String, PathBuf)>, save_as: &str) -> Response {
+    let (writer, reader) = zip::pipe();
+    let archive_name = save_as.to_string();
+    std::thread::spawn(move || {
+        let mut archive = zip::ZipWriter::new(writer);
+        for (name, path) in files.iter() {
+            let mut file = match File::open(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    warn!("Zip warning: skipped {:?}: {}", path, err);
+                    continue;
+                }
+            };
+            let metadata = file.metadata().ok();
+            let size = metadata.as_ref().map_or(0, |metadata| metadata.len());
+            let modified = metadata.and_then(|metadata| metadata.modified().ok());
+            if let Err(err) = archive.add_file(name, modified, size, &mut file) {
+                error!(
+                    "Zip failed: {} stopped at {:?}: {}",
+                    archive_name, path, err
+                );
+                return;
+            }
+        }
+        if let Err(err) = archive.finish() {
+            error!("Zip failed: {}: {}", archive_name, err);
+        }
+    });
+
+    // There's no length to send, so it goes out chunked.
+    let mut response = Response::from_data("application/zip", Vec::new())
+        .with_unique_header("content-disposition", content_disposition(save_as));
+    response.data = ResponseBody::from_reader(reader);
+    response
+}
+
+// `save_as` is the file name to fall back on if the path doesn't have a usable one.
+fn download(request: &Request, path: &Path, indexed_size: u64, save_as: &str) -> Response {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => {
+            warn!("Download failed: file doesn't exist {:?}", path);
+            return Response::empty_404();
+        }
+    };
+
+    // The file may have been swapped out since it was indexed, so go by what's on disk now.
+    // Following symlinks here means a link pointing at a newer version downloads under the
+    // newer version's name.
+    let metadata = file.metadata().ok();
+    if let Some(metadata) = &metadata {
+        if metadata.len() != indexed_size {
+            warn!(
+                "Download warning: {:?} is {} bytes but was indexed at {} bytes",
+                path,
+                metadata.len(),
+                indexed_size
+            );
+        }
+    }
+    let current_path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let save_as = current_path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(save_as);
+    let content_disposition = content_disposition(save_as);
+
+    // Ranges let an interrupted download pick up where it left off. If-Range asks for the range
+    // only if the file hasn't changed, which there's no validator to check, so it gets the
+    // whole file to be safe.
+    let len = metadata.as_ref().map(|metadata| metadata.len());
+    let range = match (len, request.header("range")) {
+        (Some(len), Some(range)) if request.header("if-range").is_none() => {
+            parse_range(range, len).map(|range| (len, range))
+        }
+        _ => None,
+    };
+    match range {
+        Some((len, Ok((start, end)))) => {
+            if let Err(err) = file.seek(SeekFrom::Start(start)) {
+                warn!("Download failed: couldn't seek {:?}: {}", path, err);
+                return get_internal_error(request);
+            }
+            let part_len = end - start + 1;
+            let mut response = Response::from_data("application/octet-stream", Vec::new())
+                .with_status_code(206)
+                .with_unique_header("content-disposition", content_disposition)
+                .with_unique_header("accept-ranges", "bytes")
+                .with_unique_header("content-range", format!("bytes {}-{}/{}", start, end, len))
+                .with_unique_header("content-length", part_len.to_string());
+            response.data =
+                ResponseBody::from_reader_and_size(file.take(part_len), part_len as usize);
+            return response;
+        }
+        Some((len, Err(()))) => {
+            return Response::text("Range Not Satisfiable")
+                .with_status_code(416)
+                .with_unique_header("content-range", format!("bytes */{}", len));
+        }
+        None => {}
+    }
+
+    let response = Response::from_file("application/octet-stream", file)
+        .with_unique_header("content-disposition", content_disposition)
+        .with_unique_header("accept-ranges", "bytes");
+    // Browsers need the length to show download progress. Set it outright instead of counting on
+    // the server library to work it out from the file.
+    match len {
+        Some(len) => response.with_unique_header("content-length", len.to_string()),
+        None => response,
+    }
+}
+
+// Parses a "range: bytes=<start>-<end>" header into the first and last byte to send, or an
+// error if the range starts past the end of the file. Returns None for anything else, including
+// multiple ranges, which get the whole file instead.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let range = header.trim().strip_prefix("bytes=")?;
+    if range.contains(',') {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        // "-500" is the last 500 bytes.
+        let suffix_len: u64 = end.parse().ok()?;
+        if suffix_len == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len - u64::min(suffix_len, len), len - 1)));
+    }
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok()?,
+    };
+    if end < start {
+        return None;
+    }
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, u64::min(end, len - 1))))
+}
+
+// Browsers that understand RFC 5987 use the percent-encoded UTF-8 `filename*`, and everything
+// else falls back to an ASCII-only `filename` with accents stripped.
+fn content_disposition(file_name: &str) -> String {
+    let ascii_fallback: String = file_name
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+
+    let mut encoded = String::new();
+    for byte in file_name.bytes() {
+        match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii_fallback, encoded
+    )
+}
+
+fn get_catalog(request: &Request, catalog: &CompressedAsset) -> Response {
+    compressed_response(request, catalog).with_public_cache(60)
+}
+
+// The whole catalog with the games saved in the catalog database, which is put together for each
+// request instead of being kept compressed in memory.
+fn get_stored_catalog(request: &Request, store: &Store, catalog: &Catalog) -> Response {
+    let games = store.listed_games().and_then(|games| {
+        games
+            .into_iter()
+            .map(|game| RawValue::from_string(game).map_err(io::Error::from))
+            .collect::<io::Result<Vec<_>>>()
+    });
+    let games = match games {
+        Ok(games) => games,
+        Err(err) => {
+            warn!("Failed to read the catalog database: {}", err);
+            return get_internal_error(request);
+        }
+    };
+    let stored = StoredCatalog {
+        indexed_at: catalog.indexed_at,
+        site_title: &catalog.site_title,
+        site_description: &catalog.site_description,
+        featured: &catalog.featured,
+        game_count: catalog.game_count,
+        games,
+        genres: &catalog.genres,
+        themes: &catalog.themes,
+    };
+    let bytes = serde_json::to_vec(&stored).unwrap();
+    dynamic_response(request, extension_to_mime("json"), bytes).with_public_cache(60)
+}
+
+// Just one game, for pages that don't need the whole catalog. It's found the same way as for
+// downloads, so hidden games can be looked up too.
+fn get_game(request: &Request, shared: &Shared, model: &Model, slug: &str) -> Response {
+    let game = match find_game(&model.catalog.games, slug) {
+        Some(game) => game,
+        None => return Response::empty_404(),
+    };
+    let store = match &shared.store {
+        Some(store) => store,
+        None => match model.games_gz.get(&game.slug) {
+            Some(game) => return compressed_response(request, game).with_public_cache(60),
+            None => return Response::empty_404(),
+        },
+    };
+    match store.game(&game.slug) {
+        Ok(Some(json)) => dynamic_response(request, extension_to_mime("json"), json.into_bytes())
+            .with_public_cache(60),
+        Ok(None) => Response::empty_404(),
+        Err(err) => {
+            warn!("Failed to read the catalog database: {}", err);
+            get_internal_error(request)
+        }
+    }
+}
+
+fn get_catalog_csv(request: &Request, csv: &CompressedAsset) -> Response {
+    compressed_response(request, csv)
+        .with_unique_header("content-disposition", content_disposition("catalog.csv"))
+        .with_public_cache(60)
+}
+
+// The catalog's games as newline-delimited JSON, one game per line, so big libraries can be
+// parsed as they stream in.
+fn get_catalog_ndjson(model: Arc<Model>) -> Response {
+    let mut response = Response::from_data("application/x-ndjson", Vec::new());
+    response.data = ResponseBody::from_reader(GameLines {
+        model,
+        next_game: 0,
+        line: Vec::new(),
+        line_read: 0,
+    });
+    response.with_public_cache(60)
+}
+
+// Serializes games one at a time as the response is read, instead of all at once up front.
+struct GameLines {
+    model: Arc<Model>,
+    next_game: usize,
+    line: Vec<u8>,
+    line_read: usize,
+}
+
+impl io::Read for GameLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.line_read == self.line.len() {
+            let game = match self.model.catalog.games.get(self.next_game) {
+                Some(game) => game,
+                None => return Ok(0),
+            };
+            self.next_game += 1;
+            if game.hidden {
+                continue;
+            }
+            self.line.clear();
+            self.line_read = 0;
+            serde_json::to_writer(&mut self.line, game)?;
+            self.line.push(b'\n');
+        }
+        let read = (&self.line[self.line_read..]).read(buf)?;
+        self.line_read += read;
+        Ok(read)
+    }
+}
+
+const SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+
+// Searches by name with `q`, filters by a genre or theme id with `genre` and `theme`, and pages
+// through the results with `offset` and `limit`. Only the slugs are sent, in order.
+fn get_search(request: &Request, shared: &Shared, model: &Model) -> Response {
+    let query = match search_query(request) {
+        Some(query) => query,
+        None => return Response::empty_400(),
+    };
+    let threshold = model.search_threshold;
+    let from_store = shared.store.as_ref().and_then(|store| {
+        store
+            .search(&query, threshold)
+            .map_err(|err| warn!("Failed to search the catalog database: {}", err))
+            .ok()
+    });
+    let slugs: Vec<String> = match from_store {
+        Some(slugs) => slugs,
+        None => search::query(&model.catalog.games, &query, threshold)
+            .into_iter()
+            .map(|game| game.slug.clone())
+            .collect(),
+    };
+    let results = serde_json::to_vec(&slugs).unwrap();
+    dynamic_response(request, "application/json", results).with_no_cache()
+}
+
+// None if any of the numbers can't be read. `limit` is kept to a page the server is willing to
+// score and send on every keystroke.
+fn search_query(request: &Request) -> Option<search::Query> {
+    fn number<T: std::str::FromStr>(request: &Request, name: &str) -> Option<Option<T>> {
+        request
+            .get_param(name)
+            .map(|value| value.parse())
+            .transpose()
+            .ok()
+    }
+    Some(search::Query {
+        text: request.get_param("q").unwrap_or_default(),
+        genre: number(request, "genre")?,
+        theme: number(request, "theme")?,
+        offset: number(request, "offset")?.unwrap_or(0),
+        limit: number(request, "limit")?.map_or(SEARCH_LIMIT, |limit: usize| {
+            limit.clamp(1, MAX_SEARCH_LIMIT)
+        }),
+    })
+}
+
+fn get_browse(request: &Request, browse: &CompressedAsset) -> Response {
+    let csp = [
+        "default-src 'none'",
+        "img-src 'self'",
+        "base-uri 'none'",
+        "form-action 'none'",
+    ];
+    compressed_response(request, browse)
+        .with_unique_header("content-security-policy", csp.join("; "))
+        .with_unique_header("referrer-policy", "no-referrer")
+        .with_public_cache(60)
+}
+
+// Brotli is smaller so it's picked whenever it's accepted, then gzip, which almost every client
+// accepts. Clients that accept neither get the uncompressed bytes instead of a body they can't
+// decode.
+fn compressed_response(request: &Request, asset: &CompressedAsset) -> Response {
+    let response = if accepts_encoding(request, "br") {
+        Response::from_data(asset.mime, asset.brotli.clone())
+            .with_unique_header("content-encoding", "br")
+            .with_etag(request, format!("{}-br", asset.hash))
+    } else if accepts_encoding(request, "gzip") {
+        Response::from_data(asset.mime, asset.gzipped.clone())
+            .with_unique_header("content-encoding", "gzip")
+            .with_etag(request, asset.hash.clone())
+    } else {
+        Response::from_data(asset.mime, asset.uncompressed.clone())
+            .with_etag(request, format!("{}-identity", asset.hash))
+    };
+    // Every version lives at the same url, so caches in between have to keep them apart.
+    response.with_unique_header("vary", "accept-encoding")
+}
+
+// Responses made for a single request, like search results, are small and never sent twice, so
+// they're only compressed into the encoding the client will use, at a fast level, and not at all
+// when they're too small for it to help.
+const SMALL_RESPONSE: usize = 1024;
+const FAST_BROTLI_QUALITY: u32 = 4;
+
+fn dynamic_response(request: &Request, mime: &'static str, bytes: Vec<u8>) -> Response {
+    let hash = encoded_hash(&bytes);
+    let response = if bytes.len() >= SMALL_RESPONSE && accepts_encoding(request, "br") {
+        Response::from_data(mime, brotli(&bytes, FAST_BROTLI_QUALITY).unwrap())
+            .with_unique_header("content-encoding", "br")
+            .with_etag(request, format!("{}-br", hash))
+    } else if bytes.len() >= SMALL_RESPONSE && accepts_encoding(request, "gzip") {
+        Response::from_data(mime, gzip(&bytes, flate2::Compression::fast()).unwrap())
+            .with_unique_header("content-encoding", "gzip")
+            .with_etag(request, format!("{}-gzip", hash))
+    } else {
+        Response::from_data(mime, bytes).with_etag(request, format!("{}-identity", hash))
+    };
+    response.with_unique_header("vary", "accept-encoding")
+}
+
+// Whether the client's accept-encoding header allows the encoding. Clients that don't send the
+// header at all (like curl without --compressed) are treated as not accepting any. Naming the
+// encoding outright wins over "*", so "gzip;q=0, *" still turns gzip down.
+fn accepts_encoding(request: &Request, encoding: &str) -> bool {
+    let accept_encoding = match request.header("accept-encoding") {
+        Some(accept_encoding) => accept_encoding,
+        None => return false,
+    };
+    let mut wildcard = None;
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or("").to_ascii_lowercase();
+        let is_disabled = params
+            .any(|param| param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+        if name == encoding || (encoding == "gzip" && name == "x-gzip") {
+            return !is_disabled;
+        }
+        if name == "*" {
+            wildcard = Some(!is_disabled);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+enum ImageSize {
+    Thumbnail,
+    Medium,
+    Original,
+}
+
+impl ImageSize {
+    // The name of the size's files in the image cache, before the extension.
+    fn file_stem(&self) -> &'static str {
+        match self {
+            ImageSize::Thumbnail => "thumbnail",
+            ImageSize::Medium => "medium",
+            ImageSize::Original => "original",
+        }
+    }
+}
+
+fn get_image(request: &Request, shared: &Shared, image_id: &str) -> Response {
+    // The id is used as a folder name in the cache, so anything like "../" could reach outside
+    // of it. Real ids never have more than letters and numbers.
+    if !igdb::is_valid_image_id(image_id) {
+        return Response::empty_400();
+    }
+    let size = match request.get_param("size").as_deref() {
+        Some("Thumbnail") => ImageSize::Thumbnail,
+        Some("Medium") => ImageSize::Medium,
+        Some("Original") => ImageSize::Original,
+        _ => return Response::empty_404(),
+    };
+
+    // Covers of games that aren't on IGDB come straight from their file, at every size.
+    if image_id.starts_with(game::LOCAL_IMAGE_PREFIX) {
+        let model = shared.model();
+        let source = model
+            .catalog
+            .games
+            .iter()
+            .filter_map(|game| game.cover.as_ref())
+            .find(|cover| cover.id == image_id)
+            .and_then(|cover| cover.source.as_ref());
+        let extension = source
+            .and_then(|source| source.extension())
+            .map_or(String::new(), |extension| {
+                extension.to_string_lossy().to_lowercase()
+            });
+        return match source.map(std::fs::File::open) {
+            Some(Ok(image)) => Response::from_file(extension_to_mime(&extension), image)
+                .with_unique_header("cache-control", "max-age=10368000, immutable"), // 10368000 seconds = 120 days
+            _ => Response::empty_404(),
+        };
+    }
+
+    let accepts_webp = request
+        .header("accept")
+        .map_or(false, |accept| accept.contains("image/webp"));
+    // Images cached before the webp versions were added only have jpegs until the prefetcher
+    // gets around to them again.
+    let cache = image_cache(image_id);
+    // Same for the medium size, which is made from the original until then.
+    let size = match size {
+        ImageSize::Medium if !cache.join("medium.jpeg").exists() => ImageSize::Original,
+        size => size,
+    };
+    let webp = cache.join(format!("{}.webp", size.file_stem()));
+    let (mime, path) = if accepts_webp && webp.exists() {
+        ("image/webp", webp)
+    } else {
+        (
+            "image/jpeg",
+            cache.join(format!("{}.jpeg", size.file_stem())),
+        )
+    };
+
+    // Every size comes in webp or jpeg depending on the accept header, so caches have to keep
+    // those apart.
+    match std::fs::File::open(path) {
+        Ok(image) => {
+            shared.cache_usage.touch(image_id);
+            Response::from_file(mime, image)
+                .with_unique_header("cache-control", "max-age=10368000, immutable") // 10368000 seconds = 120 days
+                .with_unique_header("vary", "accept")
+        }
+        Err(_) => {
+            // Images removed to keep the cache small are still in the catalog, so they're
+            // downloaded again for next time.
+            if lock(&shared.cache_usage.evicted).remove(image_id) {
+                if let Some(prefetch) = shared.prefetch() {
+                    let _ = prefetch.send(image_id.to_string());
+                }
+            }
+            Response::empty_404()
+        }
+    }
+}
+
+const CACHE_ROOT: &str = "./cache";
+
+fn image_cache(image_id: &str) -> PathBuf {
+    Path::new(CACHE_ROOT).join(image_id)
+}
+
+fn create_image_cache(image_id: &str) -> io::Result<PathBuf> {
+    let image_dir = image_cache(image_id);
+    // Prefetch workers create these concurrently. create_dir_all is fine with a directory
+    // already existing, even one that another worker created a moment ago, and it creates
+    // any missing parents too.
+    fs::create_dir_all(&image_dir)?;
+    Ok(image_dir)
+}
+
+// The largest each cached size of an image can be, as (max width, max height).
+#[derive(Clone, Copy)]
+pub struct ImageSizes {
+    pub original: (Option<u32>, Option<u32>),
+    pub thumbnail: (Option<u32>, Option<u32>),
+    pub medium: (Option<u32>, Option<u32>),
+}
+
+// The running `image_prefetch_pool`: where images are queued, the thread it runs on, and the
+// images it couldn't cache.
+pub struct Prefetcher {
+    pub queue: Sender<String>,
+    pub pool: JoinHandle<()>,
+    pub failures: Receiver<String>,
+}
+
+// Where images that aren't cached yet get downloaded from.
+#[derive(Clone)]
+pub struct ImageSource {
+    pub agent: ureq::Agent,
+    pub host: String,
+    pub retries: u32,
+}
+
+struct JobThread {
+    is_busy: bool,
+    sender: Sender<String>,
+}
+
+// Prefetching happens in two stages. Downloads are I/O-bound so `thread_count` of them run at
+// once, and each hands what it downloaded off to the processing stage, which does the CPU-bound
+// decoding, resizing, and encoding on one thread per core.
+pub fn image_prefetch_pool(
+    thread_count: usize,
+    sizes: ImageSizes,
+    source: ImageSource,
+    jobs: Receiver<String>,
+    failures: Sender<String>,
+    usage: Arc<CacheUsage>,
+) {
+    // Bounded so downloads can't get too far ahead of processing and pile up in memory.
+    let (downloaded, to_process) = bounded(thread_count);
+    let mut workers = Vec::new();
+    for _ in 0..num_cpus::get() {
+        let to_process = to_process.clone();
+        let failures = failures.clone();
+        let usage = Arc::clone(&usage);
+        workers.push(std::thread::spawn(move || {
+            image_process_worker(sizes, to_process, failures, &usage)
+        }));
+    }
+
+    let mut threads = Vec::with_capacity(thread_count);
+    let (on_complete, job_finished) = bounded(thread_count);
+    for thread in 0..thread_count {
+        let (s, r) = bounded(1);
+        let on_complete = on_complete.clone();
+        let failures = failures.clone();
+        let source = source.clone();
+        let downloaded = downloaded.clone();
+        let usage = Arc::clone(&usage);
+        workers.push(std::thread::spawn(move || {
+            image_download_worker(
+                thread,
+                &source,
+                r,
+                on_complete,
+                downloaded,
+                failures,
+                &usage,
+            )
+        }));
+        threads.push(JobThread {
+            is_busy: false,
+            sender: s,
+        });
+    }
+
+    for job in jobs.into_iter() {
+        let free_thread = threads.iter_mut().find(|thread| !thread.is_busy);
+        match free_thread {
+            Some(thread) => {
+                thread.is_busy = true;
+                thread.sender.send(job).unwrap();
+            }
+            None => {
+                // now we wait for a thread
+                let thread_index = job_finished.recv().unwrap();
+                threads[thread_index].sender.send(job).unwrap(); // no rest, get back to work lmao
+            }
+        }
+    }
+
+    // Every sender is gone once the server shuts down. Closing each stage's channel in turn lets
+    // the workers finish what they already have and stop.
+    drop(threads);
+    drop(downloaded);
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+// An image that's ready to be processed. `image` is None when the original was already cached
+// and doesn't need to be downloaded again.
+struct Download {
+    image_id: String,
+    image: Option<igdb::Image>,
+}
+
+fn image_download_worker(
+    thread: usize,
+    source: &ImageSource,
+    receiver: Receiver<String>,
+    on_complete: Sender<usize>,
+    downloaded: Sender<Download>,
+    failures: Sender<String>,
+    usage: &CacheUsage,
+) {
+    for image_id in receiver.into_iter() {
+        // Finished by the processing stage, or here if the download fails.
+        usage.start(&image_id);
+        match download_image(source, &image_id) {
+            Ok(image) => downloaded.send(Download { image_id, image }).unwrap(),
+            Err(err) => {
+                warn!("Failed to load image {}: {}", image_id, err);
+                usage.finish(&image_id);
+                failures.send(image_id).unwrap();
+            }
+        }
+        on_complete.send(thread).unwrap();
+    }
+}
+
+fn image_process_worker(
+    sizes: ImageSizes,
+    to_process: Receiver<Download>,
+    failures: Sender<String>,
+    usage: &CacheUsage,
+) {
+    for download in to_process.into_iter() {
+        let result = process_image(&download.image_id, download.image, sizes);
+        usage.finish(&download.image_id);
+        match result {
+            Ok(()) => debug!("Loaded: {}", download.image_id),
+            Err(err) => {
+                warn!("Failed to load image {}: {}", download.image_id, err);
+                failures.send(download.image_id).unwrap();
+            }
+        }
+    }
+}
+
+fn download_image(
+    source: &ImageSource,
+    image_id: &str,
+) -> Result<Option<igdb::Image>, Box<dyn std::error::Error>> {
+    // Checked before the id is used as a folder name, too.
+    if !igdb::is_valid_image_id(image_id) {
+        return Err(Box::new(igdb::ImageError::BadId(image_id.to_string())));
+    }
+    let cache = create_image_cache(image_id)?;
+    if cache.join("original.jpeg").exists() {
+        return Ok(None);
+    }
+    Ok(Some(igdb::get_image(
+        &source.agent,
+        &source.host,
+        image_id,
+        source.retries,
+    )?))
+}
+
+fn process_image(
+    image_id: &str,
+    downloaded: Option<igdb::Image>,
+    sizes: ImageSizes,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cache = image_cache(image_id);
+    let meta = downloaded.as_ref().map(|image| ImageMeta {
+        id: image_id.to_string(),
+        format: format!("{:?}", image.format),
+        fetched_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs()),
+        bytes: image.bytes.len() as u64,
+    });
+    let original_path = cache.join("original.jpeg");
+    let original = match downloaded {
+        None => image::open(&original_path)?,
+        Some(image) => {
+            let mut original = image::load_from_memory_with_format(&image.bytes[..], image.format)?;
+            let (ow, oh) = max_dimensions(original.dimensions(), sizes.original);
+            if (ow, oh) != original.dimensions() {
+                original = original.resize(ow, oh, image::imageops::FilterType::Lanczos3);
+            }
+            write_atomically(&original_path, |part| {
+                original.save_with_format(part, image::ImageFormat::Jpeg)
+            })?;
+            original
+        }
+    };
+
+    // Thumbnails cached at a different size are left over from before the size was changed in
+    // the config, so they're made again.
+    let (tw, th) = max_dimensions(original.dimensions(), sizes.thumbnail);
+    let thumbnail_dimensions = game::fit_within(original.dimensions(), sizes.thumbnail);
+    let thumbnail_path = cache.join("thumbnail.jpeg");
+    if !has_dimensions(&thumbnail_path, thumbnail_dimensions) {
+        let thumbnail = original.thumbnail(tw, th);
+        write_atomically(&thumbnail_path, |part| {
+            thumbnail.save_with_format(part, image::ImageFormat::Jpeg)
+        })?;
+    }
+
+    // image can't encode webp, so the webp thumbnail goes through libwebp instead.
+    let webp_path = cache.join("thumbnail.webp");
+    if !has_dimensions(&webp_path, thumbnail_dimensions) {
+        let thumbnail = original.thumbnail(tw, th).to_rgb8();
+        let webp =
+            webp::Encoder::from_rgb(&thumbnail, thumbnail.width(), thumbnail.height()).encode(75.0);
+        write_atomically(&webp_path, |part| fs::write(part, &*webp))?;
+    }
+
+    // Remade when the size in the config changes, like the thumbnails. An original that's already
+    // small enough is just copied.
+    let medium_dimensions = game::fit_within(original.dimensions(), sizes.medium);
+    // Only the width is limited, so a really tall image can still be too much for libwebp.
+    let is_webp_possible = medium_dimensions.0 <= 16383 && medium_dimensions.1 <= 16383;
+    let medium_path = cache.join("medium.jpeg");
+    let medium_webp_path = cache.join("medium.webp");
+    if !has_dimensions(&medium_path, medium_dimensions)
+        || (is_webp_possible && !has_dimensions(&medium_webp_path, medium_dimensions))
+    {
+        let (mw, mh) = max_dimensions(original.dimensions(), sizes.medium);
+        let medium = if (mw, mh) == original.dimensions() {
+            original.clone()
+        } else {
+            original.resize(mw, mh, FilterType::Lanczos3)
+        };
+        write_atomically(&medium_path, |part| {
+            medium.save_with_format(part, image::ImageFormat::Jpeg)
+        })?;
+        if is_webp_possible {
+            let rgb = medium.to_rgb8();
+            let webp = webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(85.0);
+            write_atomically(&medium_webp_path, |part| fs::write(part, &*webp))?;
+        }
+    }
+
+    // libwebp can't go past 16383 pixels on a side, those originals are only served as jpeg.
+    let webp_path = cache.join("original.webp");
+    let (ow, oh) = original.dimensions();
+    if !webp_path.exists() && ow <= 16383 && oh <= 16383 {
+        let rgb = original.to_rgb8();
+        let webp = webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(85.0);
+        write_atomically(&webp_path, |part| fs::write(part, &*webp))?;
+    }
+
+    // Written last so there's only ever a meta.json next to a complete set of images.
+    if let Some(meta) = meta {
+        let meta = serde_json::to_vec(&meta)?;
+        write_atomically(&cache.join("meta.json"), |part| fs::write(part, &meta))?;
+    }
+    Ok(())
+}
+
+// Written alongside each downloaded image, to tell what's in the cache and where it came from.
+#[derive(Serialize, Deserialize)]
+struct ImageMeta {
+    id: String,
+    format: String, // What IGDB sent, before it was converted to jpeg.
+    fetched_at: u64,
+    bytes: u64, // The size of what IGDB sent.
+}
+
+// Images are written to a ".part" file first and renamed into place once they're complete,
+// so a crash mid-write can't leave a truncated image in the cache. Anything in the cache
+// without the ".part" extension can be trusted to be complete.
+fn write_atomically<E>(path: &Path, write: impl FnOnce(&Path) -> Result<(), E>) -> Result<(), E>
+where
+    E: From<io::Error>,
+{
+    let part = part_path(path);
+    write(&part)?;
+    fs::rename(&part, path)?;
+    Ok(())
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    path.with_file_name(file_name)
+}
+
+// Deletes cached images that none of the games use anymore. Image ids change when the image
+// does, so without this the cache keeps every version of every image it's ever seen.
+pub fn remove_unused_images(games: &[Game]) {
+    let used: HashSet<&str> = games
+        .iter()
+        .flat_map(|game| game.screenshots.iter().chain(game.cover.as_ref()))
+        .map(|image| image.id.as_str())
+        .collect();
+    let image_dirs = match fs::read_dir(CACHE_ROOT) {
+        Ok(image_dirs) => image_dirs,
+        Err(_) => return,
+    };
+    let mut removed = 0;
+    for image_dir in image_dirs.filter_map(Result::ok) {
+        // Every image has its own dir. Files in the cache root are other things, like the
+        // compressed catalog.
+        if !image_dir.file_type().map_or(false, |t| t.is_dir()) {
+            continue;
+        }
+        let image_id = image_dir.file_name();
+        if used.contains(image_id.to_string_lossy().as_ref()) {
+            continue;
+        }
+        match fs::remove_dir_all(image_dir.path()) {
+            Ok(()) => removed += 1,
+            Err(err) => warn!("Failed to remove unused image {:?}: {}", image_id, err),
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} unused images from the cache.", removed);
+    }
+}
+
+// Clears out the ".part" files left behind by writes that were interrupted by a crash. Has to
+// run before the prefetch workers start, or it could delete a write that's in progress.
+pub fn remove_partial_images() {
+    let image_dirs = match fs::read_dir(CACHE_ROOT) {
+        Ok(image_dirs) => image_dirs,
+        Err(_) => return,
+    };
+    for image_dir in image_dirs.filter_map(Result::ok) {
+        let files = match fs::read_dir(image_dir.path()) {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+        for file in files.filter_map(Result::ok) {
+            let path = file.path();
+            if path.extension() == Some(OsStr::new("part")) {
+                if let Err(err) = fs::remove_file(&path) {
+                    warn!("Failed to remove partial image {:?}: {}", path, err);
+                }
+            }
+        }
+    }
+}
+
+// Keeps track of when each cached image was last used, so the cache can be kept under
+// `cache_max_bytes` by removing the ones that haven't been used for the longest.
+pub struct CacheUsage {
+    // Seconds since the epoch, kept in cache/accessed.json between restarts. Images that aren't
+    // in here go by when their folder was last changed instead.
+    accessed: Mutex<HashMap<String, u64>>,
+    // Images the prefetcher is downloading or processing. It stays locked while an image is
+    // being removed, so a worker can't start on an image that's halfway deleted.
+    in_progress: Mutex<HashSet<String>>,
+    evicted: Mutex<HashSet<String>>,
+}
+
+const ACCESSED: &str = "./cache/accessed.json";
+
+impl CacheUsage {
+    pub fn load() -> Self {
+        let accessed = fs::read(ACCESSED)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        CacheUsage {
+            accessed: Mutex::new(accessed),
+            in_progress: Mutex::new(HashSet::new()),
+            evicted: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn touch(&self, image_id: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        lock(&self.accessed).insert(image_id.to_string(), now);
+    }
+
+    fn start(&self, image_id: &str) {
+        lock(&self.in_progress).insert(image_id.to_string());
+        lock(&self.evicted).remove(image_id);
+    }
+
+    fn finish(&self, image_id: &str) {
+        lock(&self.in_progress).remove(image_id);
+        self.touch(image_id);
+    }
+
+    fn save(&self) {
+        let accessed = serde_json::to_vec(&*lock(&self.accessed)).unwrap();
+        if let Err(err) = write_atomically(Path::new(ACCESSED), |part| fs::write(part, &accessed)) {
+            warn!("Failed to save {}: {}", ACCESSED, err);
+        }
+    }
+}
+
+// Removes the least recently used images until the cache fits in `max_bytes`.
+fn evict_images(usage: &CacheUsage, max_bytes: u64) {
+    let image_dirs = match fs::read_dir(CACHE_ROOT) {
+        Ok(image_dirs) => image_dirs,
+        Err(_) => return,
+    };
+    let mut images: Vec<(String, u64, u64)> = {
+        let accessed = lock(&usage.accessed);
+        image_dirs
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().map_or(false, |t| t.is_dir()))
+            .map(|entry| {
+                let image_id = entry.file_name().to_string_lossy().into_owned();
+                let last_used = accessed.get(&image_id).copied().unwrap_or_else(|| {
+                    entry
+                        .metadata()
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map_or(0, |since_epoch| since_epoch.as_secs())
+                });
+                let bytes = dir_bytes(&entry.path());
+                (image_id, last_used, bytes)
+            })
+            .collect()
+    };
+    images.sort_by_key(|&(_, last_used, _)| last_used);
+
+    let mut total: u64 = images.iter().map(|&(_, _, bytes)| bytes).sum();
+    let mut removed = 0;
+    for (image_id, _, bytes) in images.iter() {
+        if total <= max_bytes {
+            break;
+        }
+        let in_progress = lock(&usage.in_progress);
+        if in_progress.contains(image_id) {
+            continue;
+        }
+        match fs::remove_dir_all(image_cache(image_id)) {
+            Ok(()) => {
+                total -= bytes;
+                removed += 1;
+                lock(&usage.evicted).insert(image_id.clone());
+            }
+            Err(err) => warn!(
+                "Failed to remove image {:?} from the cache: {}",
+                image_id, err
+            ),
+        }
+    }
+    if removed > 0 {
+        info!(
+            "Removed {} images from the cache to keep it under {} bytes.",
+            removed, max_bytes
+        );
+    }
+
+    // Forget images that aren't cached anymore, so the file doesn't grow forever either.
+    {
+        let evicted = lock(&usage.evicted);
+        let cached: HashSet<&str> = images
+            .iter()
+            .map(|(image_id, _, _)| image_id.as_str())
+            .filter(|image_id| !evicted.contains(*image_id))
+            .collect();
+        let mut accessed = lock(&usage.accessed);
+        accessed.retain(|image_id, _| cached.contains(image_id.as_str()));
+    }
+    usage.save();
+}
+
+// How much space the images in the cache are taking up, in bytes.
+pub fn cache_bytes() -> u64 {
+    fs::read_dir(CACHE_ROOT)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map_or(false, |t| t.is_dir()))
+        .map(|entry| dir_bytes(&entry.path()))
+        .sum()
+}
+
+fn dir_bytes(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|file| file.ok()?.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+// False when the image is missing or can't be read, too.
+fn has_dimensions(path: &Path, dimensions: (u32, u32)) -> bool {
+    image::image_dimensions(path).map_or(false, |actual| actual == dimensions)
+}
+
+fn max_dimensions(dimensions: (u32, u32), max: (Option<u32>, Option<u32>)) -> (u32, u32) {
+    let (mut width, mut height) = dimensions;
+    let (max_width, max_height) = max;
+    if let Some(max_width) = max_width {
+        width = u32::min(width, max_width);
+    }
+    if let Some(max_height) = max_height {
+        height = u32::min(height, max_height);
+    }
+    (width, height)
+}
+
+// Swaps the site title and description from the config into the index's <head>.
+fn branded_index(index: &[u8], config: &Config) -> Vec<u8> {
+    let mut head = format!("<title>{}</title>", escape_html(&config.site_title));
+    if let Some(description) = &config.site_description {
+        head.push_str(&format!(
+            "\n    <meta name=\"description\" content=\"{}\" />",
+            escape_html(description)
+        ));
+    }
+    String::from_utf8_lossy(index)
+        .replacen("<title>Grifter</title>", &head, 1)
+        .into_bytes()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Every size the favicon is served at. The home screen icons are the last two.
+const FAVICONS: [(&str, u32); 4] = [
+    ("/favicon.ico", 32),
+    ("/favicon.png", 32),
+    ("/icon-192.png", 192),
+    ("/icon-512.png", 512),
+];
+const APP_ICONS: &[(&str, u32)] = &[FAVICONS[2], FAVICONS[3]];
+
+// Scales the favicon and encodes it in whatever format the url's extension says.
+fn app_icon(
+    favicon: &image::DynamicImage,
+    url: &str,
+    size: u32,
+    filter: FilterType,
+) -> image::ImageResult<Vec<u8>> {
+    let format = image::ImageFormat::from_path(url)?;
+    let icon = favicon.resize_exact(size, size, filter);
+    let mut bytes = Vec::new();
+    icon.write_to(&mut bytes, format)?;
+    Ok(bytes)
+}
+
+fn games_gz(catalog: &Catalog) -> HashMap<String, CompressedAsset> {
+    catalog
+        .games
+        .iter()
+        .map(|game| (game.slug.clone(), game_gz(game)))
+        .collect()
+}
+
+fn game_gz(game: &Game) -> CompressedAsset {
+    compressed_asset(
+        extension_to_mime("json"),
+        &serde_json::to_vec(game).unwrap(),
+    )
+}
+
+#[derive(Serialize)]
+struct IndexedCatalog<'a> {
+    indexed_at: u64,
+    #[serde(flatten)]
+    catalog: &'a Catalog,
+}
+
+// `indexed_at` changes on every index, so only the rest of the catalog is fingerprinted. That
+// way the copy cached on disk is reused until the games change, which also means `indexed_at`
+// is when they last did.
+fn catalog_gz(catalog: &Catalog) -> CompressedAsset {
+    let indexed = IndexedCatalog {
+        indexed_at: catalog.indexed_at,
+        catalog,
+    };
+    cached_compressed_asset(
+        "catalog.json",
+        extension_to_mime("json"),
+        &serde_json::to_vec(&indexed).unwrap(),
+        &serde_json::to_vec(catalog).unwrap(),
+    )
+}
+
+// The catalog as it's served with a catalog database, with the games as they were saved there. See
+// `get_stored_catalog`.
+#[derive(Serialize)]
+struct StoredCatalog<'a> {
+    indexed_at: u64,
+    site_title: &'a str,
+    site_description: &'a Option<String>,
+    featured: &'a [String],
+    game_count: usize,
+    games: Vec<Box<RawValue>>,
+    genres: &'a [igdb::Genre],
+    themes: &'a [igdb::Theme],
+}
+
+// Just enough of each game to show it in a list. The full catalog has everything else.
+#[derive(Serialize)]
+struct CompactGame<'a> {
+    id: u64,
+    name: &'a str,
+    slug: &'a str,
+    cover: &'a Option<game::Image>,
+    graphics: &'a str,
+    available: bool,
+}
+
+// Leaves out `indexed_at` too, which changes on every index, so the copy cached on disk can
+// usually be reused.
+#[derive(Serialize)]
+struct CompactCatalog<'a> {
+    site_title: &'a str,
+    featured: &'a [String],
+    game_count: usize,
+    games: Vec<CompactGame<'a>>,
+    genres: &'a [igdb::Genre],
+    themes: &'a [igdb::Theme],
+}
+
+fn compact_catalog_gz(catalog: &Catalog) -> CompressedAsset {
+    let compact = CompactCatalog {
+        site_title: &catalog.site_title,
+        featured: &catalog.featured,
+        game_count: catalog.game_count,
+        games: catalog
+            .games
+            .iter()
+            .filter(|game| !game.hidden)
+            .map(|game| CompactGame {
+                id: game.id,
+                name: &game.name,
+                slug: &game.slug,
+                cover: &game.cover,
+                graphics: &game.graphics,
+                available: game.available,
+            })
+            .collect(),
+        genres: &catalog.genres,
+        themes: &catalog.themes,
+    };
+    let bytes = serde_json::to_vec(&compact).unwrap();
+    cached_compressed_asset(
+        "catalog-compact.json",
+        extension_to_mime("json"),
+        &bytes,
+        &bytes,
+    )
+}
+
+// The library as a spreadsheet, with genres and themes by name instead of id.
+fn catalog_csv_gz(catalog: &Catalog) -> CompressedAsset {
+    let names = |ids: &[u64], named: &[(u64, &str)]| -> String {
+        let names: Vec<&str> = ids
+            .iter()
+            .filter_map(|id| named.iter().find(|(named_id, _)| named_id == id))
+            .map(|&(_, name)| name)
+            .collect();
+        names.join(", ")
+    };
+    let genres: Vec<(u64, &str)> = catalog
+        .genres
+        .iter()
+        .map(|genre| (genre.id, genre.name.as_str()))
+        .collect();
+    let themes: Vec<(u64, &str)> = catalog
+        .themes
+        .iter()
+        .map(|theme| (theme.id, theme.name.as_str()))
+        .collect();
+
+    let mut csv = String::from("name,slug,genres,themes,size_bytes,version,stores\r\n");
+    for game in catalog.games.iter().filter(|game| !game.hidden) {
+        let stores: Vec<String> = game
+            .stores
+            .iter()
+            .map(|store| format!("{}: {}", store.name, store.url))
+            .collect();
+        let row = [
+            game.name.clone(),
+            game.slug.clone(),
+            names(&game.genres, &genres),
+            names(&game.themes, &themes),
+            game.size_bytes.to_string(),
+            game.version.clone().unwrap_or_default(),
+            stores.join("; "),
+        ];
+        let row: Vec<String> = row.iter().map(|field| escape_csv(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    compressed_asset("text/csv; charset=utf-8", csv.as_bytes())
+}
+
+// Quotes a field if it has anything that would otherwise break up the row, per RFC 4180.
+fn escape_csv(field: &str) -> String {
+    if field.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// A plain HTML list of the catalog for crawlers, text browsers, and anything else without
+// javascript.
+fn browse_gz(catalog: &Catalog) -> CompressedAsset {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\" />\n<title>{}</title>\n",
+        escape_html(&catalog.site_title)
+    );
+    if let Some(description) = &catalog.site_description {
+        html.push_str(&format!(
+            "<meta name=\"description\" content=\"{}\" />\n",
+            escape_html(description)
+        ));
+    }
+    html.push_str(&format!(
+        "</head>\n<body>\n<h1>{}</h1>\n<ul>\n",
+        escape_html(&catalog.site_title)
+    ));
+    for game in catalog.games.iter().filter(|game| !game.hidden) {
+        html.push_str("<li>\n");
+        if let Some(cover) = &game.cover {
+            html.push_str(&format!(
+                "<img src=\"/api/image/{}?size=Thumbnail\" width=\"{}\" height=\"{}\" alt=\"{} cover art\" />\n",
+                escape_html(&cover.id),
+                cover.thumbnail_width,
+                cover.thumbnail_height,
+                escape_html(&game.name)
+            ));
+        }
+        html.push_str(&format!(
+            "<a href=\"/games/{}\">{}</a>\n",
+            escape_html(&game.slug),
+            escape_html(&game.name)
+        ));
+        if game.available {
+            html.push_str(&format!(
+                "<a href=\"/api/download/{}\" download>Download</a>\n",
+                escape_html(&game.slug)
+            ));
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    compressed_asset(extension_to_mime("html"), html.as_bytes())
+}
+
+// Compressing a big catalog at the best compression is slow, so the result is kept in the cache
+// dir between restarts and reused as long as the uncompressed bytes haven't changed.
+// The copy on disk is reused as long as `fingerprinted` is the same as when it was cached, even
+// if the rest of `bytes` isn't. Everything is served from the cached copy then, so every encoding
+// still has the same bytes.
+fn cached_compressed_asset(
+    name: &str,
+    mime: &'static str,
+    bytes: &[u8],
+    fingerprinted: &[u8],
+) -> CompressedAsset {
+    let gz_path = Path::new(CACHE_ROOT).join(format!("{}.gz", name));
+    let br_path = Path::new(CACHE_ROOT).join(format!("{}.br", name));
+    let fingerprint_path = Path::new(CACHE_ROOT).join(format!("{}.gz.fingerprint", name));
+    let fingerprint = encoded_hash(fingerprinted);
+
+    let is_fresh = fs::read_to_string(&fingerprint_path).map_or(false, |f| f == fingerprint);
+    if is_fresh {
+        if let (Ok(gzipped), Ok(brotli)) = (fs::read(&gz_path), fs::read(&br_path)) {
+            if let Ok(uncompressed) = gunzip(&gzipped) {
+                return CompressedAsset {
+                    mime,
+                    hash: encoded_hash(&gzipped),
+                    gzipped,
+                    brotli,
+                    uncompressed,
+                };
+            }
+        }
+    }
+
+    let asset = compressed_asset(mime, bytes);
+    let cached = fs::create_dir_all(CACHE_ROOT)
+        .and_then(|_| write_atomically(&gz_path, |part| fs::write(part, &asset.gzipped)))
+        .and_then(|_| write_atomically(&br_path, |part| fs::write(part, &asset.brotli)))
+        .and_then(|_| write_atomically(&fingerprint_path, |part| fs::write(part, &fingerprint)));
+    if let Err(err) = cached {
+        warn!("Failed to cache {}: {}", name, err);
+    }
+    asset
+}
+
+fn compressed_asset(mime: &'static str, bytes: &[u8]) -> CompressedAsset {
+    let gzipped = gzip(bytes, flate2::Compression::best()).unwrap();
+    CompressedAsset {
+        mime,
+        hash: encoded_hash(&gzipped),
+        gzipped,
+        brotli: brotli(bytes, BROTLI_QUALITY).unwrap(),
+        uncompressed: bytes.to_vec(),
+    }
+}
+
+fn encoded_hash(bytes: &[u8]) -> String {
+    use blake2::digest::{Update, VariableOutput};
+    use blake2::VarBlake2b;
+
+    let mut hash = String::new();
+    let mut hasher = VarBlake2b::new(10).unwrap();
+    hasher.update(bytes);
+    hasher.finalize_variable(|hash_bytes| {
+        let config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        hash = base64::encode_config(hash_bytes, config);
+    });
+    hash
+}
+
+pub fn gzip(bytes: &[u8], level: flate2::Compression) -> io::Result<Vec<u8>> {
+    use flate2::write::GzEncoder;
+
+    let mut encoder = GzEncoder::new(Vec::new(), level);
     encoder.write_all(bytes)?;
     encoder.finish()
 }
+
+fn gunzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut uncompressed = Vec::new();
+    flate2::read::GzDecoder::new(bytes).read_to_end(&mut uncompressed)?;
+    Ok(uncompressed)
+}
+
+// Quality 11 is a little smaller again, but many times slower, which adds up over the catalog
+// and every game on each reload.
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+fn brotli(bytes: &[u8], quality: u32) -> io::Result<Vec<u8>> {
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, quality, BROTLI_WINDOW_BITS);
+    encoder.write_all(bytes)?;
+    Ok(encoder.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(url: &str, headers: &[(&str, &str)]) -> Request {
+        let headers = headers
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Request::fake_http("GET", url, headers, Vec::new())
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &**value)
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn api_routes_turn_away_other_methods() {
+        let routes = [
+            ("/api/reload", "POST, OPTIONS"),
+            ("/api/catalog", "GET, HEAD, OPTIONS"),
+            ("/api/catalog.ndjson", "GET, HEAD, OPTIONS"),
+            ("/api/catalog.csv", "GET, HEAD, OPTIONS"),
+            ("/api/search", "GET, HEAD, OPTIONS"),
+            ("/api/warnings", "GET, HEAD, OPTIONS"),
+            ("/api/cache", "GET, HEAD, OPTIONS"),
+            ("/api/health", "GET, HEAD, OPTIONS"),
+            ("/api/ready", "GET, HEAD, OPTIONS"),
+            ("/healthz", "GET, HEAD, OPTIONS"),
+            ("/readyz", "GET, HEAD, OPTIONS"),
+            ("/api/download/celeste", "GET, HEAD, OPTIONS"),
+            ("/api/download/celeste/extras/0", "GET, HEAD, OPTIONS"),
+            ("/api/download/celeste/dlc/0", "GET, HEAD, OPTIONS"),
+            ("/api/download-all/celeste", "GET, HEAD, OPTIONS"),
+            ("/api/checksum/celeste", "GET, HEAD, OPTIONS"),
+            ("/api/game/celeste", "GET, HEAD, OPTIONS"),
+            ("/api/image/co1abc", "GET, HEAD, OPTIONS"),
+        ];
+        for &(url, allowed) in routes.iter() {
+            for &method in ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH"].iter() {
+                let request = Request::fake_http(method, url, Vec::new(), Vec::new());
+                match check_method(&request) {
+                    None => assert!(allowed.contains(method), "{} {}", method, url),
+                    Some(response) => {
+                        assert!(!allowed.contains(method), "{} {}", method, url);
+                        assert_eq!(response.status_code, 405, "{} {}", method, url);
+                        assert_eq!(header(&response, "allow"), Some(allowed));
+                    }
+                }
+            }
+            let request = Request::fake_http("OPTIONS", url, Vec::new(), Vec::new());
+            let response = check_method(&request).unwrap();
+            assert_eq!(response.status_code, 204);
+            assert_eq!(header(&response, "allow"), Some(allowed));
+        }
+
+        // Pages that aren't part of the API go to the index whatever the method.
+        let request = Request::fake_http("POST", "/games/celeste", Vec::new(), Vec::new());
+        assert!(check_method(&request).is_none());
+    }
+
+    #[test]
+    fn head_requests_are_answered_like_a_get() {
+        let head = Request::fake_https_from(
+            "192.0.2.1:4242".parse().unwrap(),
+            "HEAD",
+            "/api/download/celeste?token=1",
+            vec![("Range".to_string(), "bytes=0-99".to_string())],
+            Vec::new(),
+        );
+        let get = as_get(&head);
+        assert_eq!(get.method(), "GET");
+        assert_eq!(get.raw_url(), "/api/download/celeste?token=1");
+        assert_eq!(get.header("range"), Some("bytes=0-99"));
+        assert_eq!(get.remote_addr(), head.remote_addr());
+        assert!(get.is_secure());
+    }
+
+    #[test]
+    fn accepts_encoding_honors_q_zero() {
+        let accepts = |header: &str, encoding: &str| {
+            accepts_encoding(&get("/", &[("accept-encoding", header)]), encoding)
+        };
+        assert!(!accepts_encoding(&get("/", &[]), "gzip"));
+        assert!(accepts("gzip, deflate, br", "gzip"));
+        assert!(accepts("GZIP", "gzip"));
+        assert!(accepts("x-gzip", "gzip"));
+        assert!(accepts("br;q=1.0, gzip;q=0.5", "br"));
+        assert!(!accepts("deflate", "gzip"));
+        assert!(!accepts("gzip;q=0", "gzip"));
+        assert!(!accepts("gzip; q=0.000", "gzip"));
+        assert!(accepts("*", "br"));
+        assert!(!accepts("*;q=0", "br"));
+        assert!(!accepts("gzip;q=0, *", "gzip"));
+        assert!(accepts("*;q=0, gzip", "gzip"));
+        assert!(accepts("identity, *", "br"));
+    }
+
+    #[test]
+    fn compressed_response_sends_what_the_client_accepts() {
+        let asset = compressed_asset("text/plain", b"hello hello hello");
+        let br = compressed_response(&get("/", &[("accept-encoding", "gzip, br")]), &asset);
+        assert_eq!(header(&br, "content-encoding"), Some("br"));
+        assert_eq!(header(&br, "vary"), Some("accept-encoding"));
+        let gzip = compressed_response(&get("/", &[("accept-encoding", "gzip, br;q=0")]), &asset);
+        assert_eq!(header(&gzip, "content-encoding"), Some("gzip"));
+        let identity = compressed_response(&get("/", &[]), &asset);
+        assert_eq!(header(&identity, "content-encoding"), None);
+        assert_eq!(body(identity), b"hello hello hello");
+    }
+
+    #[test]
+    fn dynamic_response_only_compresses_what_is_worth_it() {
+        let small = b"[\"celeste\"]".to_vec();
+        let response = dynamic_response(&get("/", &[("accept-encoding", "br")]), "a/b", small);
+        assert_eq!(header(&response, "content-encoding"), None);
+        assert_eq!(header(&response, "vary"), Some("accept-encoding"));
+        assert_eq!(body(response), b"[\"celeste\"]");
+
+        let large = "\"celeste\",".repeat(200).into_bytes();
+        let br = dynamic_response(
+            &get("/", &[("accept-encoding", "gzip, br")]),
+            "a/b",
+            large.clone(),
+        );
+        assert_eq!(header(&br, "content-encoding"), Some("br"));
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&body(br)[..], 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, large);
+
+        let gzip = dynamic_response(
+            &get("/", &[("accept-encoding", "gzip")]),
+            "a/b",
+            large.clone(),
+        );
+        assert_eq!(header(&gzip, "content-encoding"), Some("gzip"));
+        assert_eq!(gunzip(&body(gzip)).unwrap(), large);
+
+        let identity = dynamic_response(&get("/", &[]), "a/b", large.clone());
+        assert_eq!(header(&identity, "content-encoding"), None);
+        assert_eq!(body(identity), large);
+    }
+
+    #[test]
+    fn search_query_keeps_the_limit_in_bounds() {
+        let query = |url: &str| search_query(&get(url, &[]));
+        let default = query("/api/search?q=zelda").unwrap();
+        assert_eq!(default.text, "zelda");
+        assert_eq!((default.offset, default.limit), (0, SEARCH_LIMIT));
+        let paged = query("/api/search?genre=12&theme=1&offset=40&limit=50").unwrap();
+        assert_eq!((paged.genre, paged.theme), (Some(12), Some(1)));
+        assert_eq!((paged.offset, paged.limit), (40, 50));
+        assert_eq!(
+            query("/api/search?limit=1000000").unwrap().limit,
+            MAX_SEARCH_LIMIT
+        );
+        assert_eq!(query("/api/search?limit=0").unwrap().limit, 1);
+
+        assert!(query("/api/search?genre=action").is_none());
+        assert!(query("/api/search?offset=-1").is_none());
+        assert!(query("/api/search?limit=lots").is_none());
+    }
+
+    #[test]
+    fn content_disposition_has_an_ascii_fallback() {
+        assert_eq!(
+            content_disposition("Celeste v1.4.zip"),
+            "attachment; filename=\"Celeste v1.4.zip\"; filename*=UTF-8''Celeste%20v1.4.zip"
+        );
+        assert_eq!(
+            content_disposition("Ōkami.exe"),
+            "attachment; filename=\"Okami.exe\"; filename*=UTF-8''%C5%8Ckami.exe"
+        );
+        assert_eq!(
+            content_disposition("東方.zip"),
+            "attachment; filename=\"__.zip\"; filename*=UTF-8''%E6%9D%B1%E6%96%B9.zip"
+        );
+        assert_eq!(
+            content_disposition("Pokémon \"Red\".zip"),
+            "attachment; filename=\"Pokemon _Red_.zip\"; filename*=UTF-8''Pok%C3%A9mon%20%22Red%22.zip"
+        );
+        // Nothing in a file name can end the header early.
+        let header = content_disposition("Red.zip\r\nset-cookie: x=1\\");
+        assert_eq!(
+            header,
+            "attachment; filename=\"Red.zip__set-cookie: x=1_\"; filename*=UTF-8''Red.zip%0D%0Aset-cookie%3A%20x%3D1%5C"
+        );
+        assert!(!header.contains(&['\r', '\n'][..]));
+    }
+
+    #[test]
+    fn download_sends_the_file_as_it_is_now() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.zip");
+        fs::write(&path, b"newer and longer").unwrap();
+        let response = download(&get("/api/download/game", &[]), &path, 5, "game");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(header(&response, "content-length"), Some("16"));
+        assert_eq!(body(response), b"newer and longer");
+
+        let missing = download(&get("/", &[]), &dir.path().join("gone.zip"), 5, "gone");
+        assert_eq!(missing.status_code, 404);
+    }
+
+    #[test]
+    fn parse_range_reads_single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range(" bytes=100-", 1000), Some(Ok((100, 999))));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=999-999", 1000), Some(Ok((999, 999))));
+        // Suffix ranges count back from the end, and can ask for more than there is.
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+    }
+
+    #[test]
+    fn parse_range_sends_the_whole_file_for_what_it_doesnt_handle() {
+        assert_eq!(parse_range("bytes=0-99, 200-299", 1000), None);
+        assert_eq!(parse_range("bytes=-100,0-1", 1000), None);
+        assert_eq!(parse_range("items=0-99", 1000), None);
+        assert_eq!(parse_range("bytes=99-0", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+        assert_eq!(parse_range("bytes=100", 1000), None);
+    }
+
+    #[test]
+    fn parse_range_rejects_ranges_past_the_end() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=5000-6000", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Err(())));
+        assert_eq!(parse_range("bytes=-100", 0), Some(Err(())));
+    }
+
+    #[test]
+    fn download_sends_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.zip");
+        fs::write(&path, b"0123456789").unwrap();
+        let download_range =
+            |range: &str| download(&get("/", &[("range", range)]), &path, 10, "game");
+
+        let response = download_range("bytes=-3");
+        assert_eq!(response.status_code, 206);
+        assert_eq!(header(&response, "content-range"), Some("bytes 7-9/10"));
+        assert_eq!(header(&response, "content-length"), Some("3"));
+        assert_eq!(body(response), b"789");
+
+        let response = download_range("bytes=0-1,5-6");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(body(response), b"0123456789");
+
+        let response = download_range("bytes=10-");
+        assert_eq!(response.status_code, 416);
+        assert_eq!(header(&response, "content-range"), Some("bytes */10"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn download_is_named_after_the_symlinked_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("Game v1.2.zip");
+        let link = dir.path().join("game.zip");
+        fs::write(&target, b"game").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let response = download(&get("/api/download/game", &[]), &link, 4, "game");
+        let disposition = header(&response, "content-disposition").unwrap();
+        assert!(
+            disposition.contains("filename=\"Game v1.2.zip\""),
+            "{}",
+            disposition
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// Hashing a game means reading all of it, which takes a while for big games, so checksums are
+// kept in cache/checksums.json and only worked out again when a file's size or modification
+// time changes. They're BLAKE2b, the same as `b2sum` prints.
+const CHECKSUMS: &str = "./cache/checksums.json";
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Checksums {
+    files: HashMap<String, Checksum>, // By path
+}
+
+#[derive(Serialize, Deserialize)]
+struct Checksum {
+    size: u64,
+    modified: u64, // Milliseconds since the Unix epoch
+    blake2b: String,
+}
+
+impl Checksums {
+    pub fn load() -> Self {
+        fs::read(CHECKSUMS)
+            .ok()
+            .and_then(|checksums| serde_json::from_slice(&checksums).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let checksums = serde_json::to_vec(self)?;
+        let part = format!("{}.part", CHECKSUMS);
+        fs::create_dir_all(Path::new(CHECKSUMS).parent().unwrap())?;
+        fs::write(&part, checksums)?;
+        fs::rename(&part, CHECKSUMS)
+    }
+
+    // The checksum of the file as it is now, if it's been hashed since it last changed.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        let (size, modified) = fingerprint(path)?;
+        let checksum = self.files.get(path.to_str()?)?;
+        if checksum.size == size && checksum.modified == modified {
+            Some(&checksum.blake2b)
+        } else {
+            None
+        }
+    }
+
+    pub fn compute(&mut self, path: &Path) -> io::Result<String> {
+        if let Some(blake2b) = self.get(path) {
+            return Ok(blake2b.to_string());
+        }
+        let (size, modified) = fingerprint(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file is missing"))?;
+        let blake2b = hash_file(path)?;
+        if let Some(key) = path.to_str() {
+            let checksum = Checksum {
+                size,
+                modified,
+                blake2b: blake2b.clone(),
+            };
+            self.files.insert(key.to_string(), checksum);
+        }
+        Ok(blake2b)
+    }
+}
+
+fn fingerprint(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_millis() as u64))
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    use blake2::{Blake2b, Digest};
+
+    let mut file = File::open(path)?;
+    let mut hasher = Blake2b::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
@@ -1,16 +1,24 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::game;
+use crate::igdb;
+use crate::log;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Clone)]
 pub enum Warning {
     ConflictingGames(Vec<Game>),
     MissingExe(Game),
+    MissingExtra(Game, Extra),
+    MissingDlc(Game, Dlc),
     UnusedExe(OsString),
+    PrivilegedPort(u16),
 }
 
 impl fmt::Display for Warning {
@@ -23,18 +31,80 @@ impl fmt::Display for Warning {
                 games[0]
             ),
             Warning::MissingExe(game) => write!(f, "game path {:?} doesn't exist", game.path),
+            Warning::MissingExtra(game, extra) => write!(
+                f,
+                "extra {:?} for \"{}\" doesn't exist",
+                extra.path,
+                game.slug()
+            ),
+            Warning::MissingDlc(game, dlc) => write!(
+                f,
+                "DLC {:?} for \"{}\" doesn't exist",
+                dlc.path,
+                game.slug()
+            ),
             Warning::UnusedExe(path) => write!(f, "{:?} exists in root dir but isn't used", path),
+            Warning::PrivilegedPort(port) => write!(
+                f,
+                "port {} needs root or the CAP_NET_BIND_SERVICE capability, which grifter doesn't \
+                seem to have, so it probably won't be able to listen on it",
+                port
+            ),
+        }
+    }
+}
+
+impl Warning {
+    // Stable names for tools to match on, since the messages can change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::ConflictingGames(_) => "conflicting_games",
+            Warning::MissingExe(_) => "missing_file",
+            Warning::MissingExtra(..) => "missing_extra",
+            Warning::MissingDlc(..) => "missing_dlc",
+            Warning::UnusedExe(_) => "unused_file",
+            Warning::PrivilegedPort(_) => "privileged_port",
         }
     }
 }
 
+impl Serialize for Warning {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            Warning::ConflictingGames(games) => {
+                map.serialize_entry("slug", &games[0].slug())?;
+                let paths: Vec<&PathBuf> = games.iter().map(|game| &game.path).collect();
+                map.serialize_entry("paths", &paths)?;
+            }
+            Warning::MissingExe(game) => {
+                map.serialize_entry("slug", &game.slug())?;
+                map.serialize_entry("path", &game.path)?;
+            }
+            Warning::MissingExtra(game, extra) => {
+                map.serialize_entry("slug", &game.slug())?;
+                map.serialize_entry("path", &extra.path)?;
+            }
+            Warning::MissingDlc(game, dlc) => {
+                map.serialize_entry("slug", &game.slug())?;
+                map.serialize_entry("path", &dlc.path)?;
+            }
+            Warning::UnusedExe(path) => map.serialize_entry("path", &PathBuf::from(path))?,
+            Warning::PrivilegedPort(port) => map.serialize_entry("port", port)?,
+        }
+        map.end()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("failed to parse toml")]
     BadToml(toml::de::Error),
 
-    #[error("bad root")]
-    BadRoot(std::io::Error),
+    #[error("root folder {0:?} couldn't be read")]
+    BadRoot(PathBuf, std::io::Error),
 
     #[error("not finished setting up")]
     NotFinishedSettingUp,
@@ -43,30 +113,337 @@ pub enum Error {
     BadSsl {
         missing_certificate: bool,
         missing_private_key: bool,
+        empty_certificate: bool,
+        empty_private_key: bool,
     },
+
+    #[error("{0}")]
+    BadPort(String),
+
+    #[error("the game {0:?} needs either a slug or a name")]
+    MissingName(PathBuf),
+
+    #[error("games_file {0:?} couldn't be read")]
+    MissingGamesFile(PathBuf, std::io::Error),
+
+    #[error("games_file {0:?} couldn't be parsed")]
+    BadGamesFile(PathBuf, toml::de::Error),
+
+    #[error("{field} is set to \"${{{variable}}}\" but that environment variable isn't set")]
+    MissingEnv {
+        field: &'static str,
+        variable: String,
+    },
+
+    #[error("{0}")]
+    BadAuth(String),
+}
+
+impl Error {
+    // Stable names for tools to match on, since the messages can change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::BadToml(_) => "bad_toml",
+            Error::BadRoot(..) => "bad_root",
+            Error::NotFinishedSettingUp => "not_finished_setting_up",
+            Error::BadSsl { .. } => "bad_ssl",
+            Error::BadPort(_) => "bad_port",
+            Error::MissingName(_) => "missing_name",
+            Error::MissingGamesFile(..) => "missing_games_file",
+            Error::BadGamesFile(..) => "bad_games_file",
+            Error::MissingEnv { .. } => "missing_env",
+            Error::BadAuth(_) => "bad_auth",
+        }
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        match self {
+            Error::BadToml(err) => {
+                map.serialize_entry("message", &err.to_string())?;
+                // toml counts from 0, editors count from 1.
+                if let Some((line, column)) = err.line_col() {
+                    map.serialize_entry("line", &(line + 1))?;
+                    map.serialize_entry("column", &(column + 1))?;
+                }
+            }
+            Error::BadRoot(_, err) => {
+                map.serialize_entry("message", &format!("{}: {}", self, err))?;
+            }
+            Error::NotFinishedSettingUp | Error::BadPort(_) | Error::BadAuth(_) => {
+                map.serialize_entry("message", &self.to_string())?
+            }
+            Error::MissingName(path) => {
+                map.serialize_entry("message", &self.to_string())?;
+                map.serialize_entry("path", path)?;
+            }
+            Error::BadSsl {
+                missing_certificate,
+                missing_private_key,
+                empty_certificate,
+                empty_private_key,
+            } => {
+                map.serialize_entry("message", &self.to_string())?;
+                map.serialize_entry("missing_certificate", missing_certificate)?;
+                map.serialize_entry("missing_private_key", missing_private_key)?;
+                map.serialize_entry("empty_certificate", empty_certificate)?;
+                map.serialize_entry("empty_private_key", empty_private_key)?;
+            }
+            Error::MissingGamesFile(path, err) => {
+                map.serialize_entry("message", &format!("{}: {}", self, err))?;
+                map.serialize_entry("path", path)?;
+            }
+            Error::BadGamesFile(path, err) => {
+                map.serialize_entry("message", &format!("{}: {}", self, err))?;
+                map.serialize_entry("path", path)?;
+                if let Some((line, column)) = err.line_col() {
+                    map.serialize_entry("line", &(line + 1))?;
+                    map.serialize_entry("column", &(column + 1))?;
+                }
+            }
+            Error::MissingEnv { field, variable } => {
+                map.serialize_entry("message", &self.to_string())?;
+                map.serialize_entry("field", field)?;
+                map.serialize_entry("variable", variable)?;
+            }
+        }
+        map.end()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Game {
     pub path: PathBuf,
-    pub slug: String,
+    // Games that aren't on IGDB leave this out and describe themselves with the fields below
+    // marked "without a slug". IGDB's number for the game works here too. See `slug()`.
+    pub slug: Option<igdb::GameRef>,
+    // Shown instead of IGDB's name. IGDB's name is still searchable. Required without a slug.
+    pub name: Option<String>,
+    // Hidden games are left out of the catalog, but can still be downloaded by direct link.
+    #[serde(default)]
+    pub hidden: bool,
+    // Replaces IGDB's summary. Games without a slug can call it "summary".
+    #[serde(alias = "summary")]
+    pub summary_override: Option<String>,
+    // Added to the end of the summary, like a note about how to get the game running.
+    pub summary_append: Option<String>,
+    // Shown alongside the tags from `tag_keywords`.
+    #[serde(default)]
+    pub extra_tags: Vec<String>,
+    // Other files that go with the game, like a manual or a patch.
+    #[serde(default)]
+    pub extras: Vec<Extra>,
+    // Expansions and add-ons, listed apart from the extras.
+    #[serde(default)]
+    pub dlc: Vec<Dlc>,
+    // Without a slug: an image file to use as the cover, relative to where grifter is run from.
+    pub cover: Option<PathBuf>,
+    // Without a slug: IGDB genre ids, so the game shows up when filtering by them.
+    #[serde(default)]
+    pub genres: Vec<u64>,
+}
+
+impl Game {
+    // The slug the game goes by on the site. Games without one get one made from their name.
+    pub fn slug(&self) -> String {
+        if let Some(slug) = &self.slug {
+            return slug.to_string();
+        }
+        let name = self.name.as_deref().unwrap_or_default();
+        match game::normalize(name).replace(' ', "-") {
+            // Names without any ascii letters or numbers, like ones in Japanese.
+            slug if slug.is_empty() => {
+                game::normalize(&self.path.to_string_lossy()).replace(' ', "-")
+            }
+            slug => slug,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Extra {
+    pub path: PathBuf, // Relative to "root", like the game's path.
+    pub label: String,
+    // What kind of file it is, like "Manual", "Patch", or "Soundtrack". Extras of the same kind
+    // are grouped together.
+    #[serde(rename = "type", default = "default_extra_kind")]
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Dlc {
+    pub path: PathBuf, // Relative to "root", like the game's path.
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Auth {
+    pub users: Vec<User>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub password_hash: String, // bcrypt, from `grifter --hash-password`.
+}
+
+// Games with any of these IGDB keywords are shown with this graphics style, like "Pixelated".
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GraphicsStyle {
+    pub name: String,
+    pub keywords: Vec<u64>,
+}
+
+// An IGDB website category to show as a store link, like Steam or GOG.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoreLink {
+    pub name: String,
+    pub category: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub im_finished_setting_up: bool,
-    pub root: PathBuf,
+    // One folder or a list of them, like when games are split across drives. See `resolve`.
+    #[serde(deserialize_with = "deserialize_roots")]
+    pub root: Vec<PathBuf>,
+    // Secrets can be left empty and set with GRIFTER_* environment variables instead, or be
+    // "${NAME}" to read them from any other environment variable. See resolve_secret.
+    #[serde(default)]
     pub twitch_client_id: String,
+    #[serde(default)]
     pub twitch_client_secret: String,
     #[serde(default)]
     pub games: Vec<Game>,
-    pub address: String,
+    // More [[games]] in a file of their own, added to the ones in this config.
+    pub games_file: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub address: IpAddr,
     pub http_port: u16,
     pub https_port: u16,
 
     pub https: bool,
     pub ssl_certificate: PathBuf,
     pub ssl_private_key: PathBuf,
+    // Listen on this Unix socket instead, for a reverse proxy on the same machine. The address,
+    // ports, and https are all left unused then. See `relay::serve_unix`.
+    pub unix_socket: Option<PathBuf>,
+    #[serde(default = "default_unix_socket_mode")]
+    pub unix_socket_mode: u32,
+    // Close connections after this long with nothing sent either way. See `relay::relay`.
+    pub idle_timeout_seconds: Option<u64>,
+    // Trust the X-Forwarded-* headers set by a reverse proxy in front of the server.
+    #[serde(default)]
+    pub behind_proxy: bool,
+    // Only clients in one of these ranges are let in. Everyone is when it's empty.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<Cidr>,
+
+    // Read game metadata from this JSON file instead of IGDB.
+    pub metadata_file: Option<PathBuf>,
+
+    #[serde(default = "default_site_title")]
+    pub site_title: String,
+    pub site_description: Option<String>,
+    // Replaces the built in favicon and home screen icons.
+    pub favicon: Option<PathBuf>,
+
+    // Slugs of games to highlight, in the order they should be shown.
+    #[serde(default)]
+    pub featured: Vec<String>,
+    // List games that couldn't be found on IGDB anyway, with only what's in the config.
+    #[serde(default)]
+    pub show_unresolved: bool,
+
+    #[serde(default)]
+    pub tag_keywords: Vec<u64>,
+    // Checked in order, so a game with keywords from more than one style gets the first.
+    #[serde(
+        default = "default_graphics_styles",
+        deserialize_with = "deserialize_graphics_styles"
+    )]
+    pub graphics_styles: Vec<GraphicsStyle>,
+    // IGDB genre id or slug to the name to show instead. Added to the defaults, see
+    // `genre_names`.
+    #[serde(default)]
+    pub genre_overrides: BTreeMap<String, String>,
+    #[serde(default = "default_store_links")]
+    pub store_links: Vec<StoreLink>,
+    // Also link to websites that IGDB hasn't verified. Trusted links still win when both exist.
+    #[serde(default)]
+    pub include_untrusted_links: bool,
+
+    // Originals bigger than this get scaled down (keeping their aspect ratio) before they're cached.
+    pub original_max_width: Option<u32>,
+    pub original_max_height: Option<u32>,
+    // Thumbnails are what's shown in the list of games. See `thumbnail_max`.
+    pub thumbnail_max_width: Option<u32>,
+    pub thumbnail_max_height: Option<u32>,
+    // The medium size is what's shown on each game's page, instead of the whole original.
+    #[serde(default = "default_medium_max_width")]
+    pub medium_max_width: u32,
+    // Where covers and screenshots are downloaded from, for mirrors of IGDB's image server.
+    #[serde(default = "default_image_host")]
+    pub image_host: String,
+    // Only keep this many screenshots per game. Covers aren't counted.
+    pub max_screenshots: Option<usize>,
+    // Delete cached images that no game uses anymore whenever the games are indexed.
+    #[serde(default)]
+    pub remove_unused_images: bool,
+    // The most space cached images can take up before the least recently used ones are removed.
+    pub cache_max_bytes: Option<u64>,
+
+    // Required by admin endpoints like /api/reload, sent as "authorization: Bearer <token>".
+    // Admin endpoints are disabled when this isn't set.
+    pub admin_token: Option<String>,
+    // Everything but the health checks asks for one of these usernames and passwords when set.
+    pub auth: Option<Auth>,
+
+    // How similar (from 0 to 1) a name has to be to a search to show up when it's misspelled.
+    #[serde(default = "default_search_threshold")]
+    pub search_threshold: f64,
+    // Serve searches, the catalog, and single games from this SQLite database instead of from
+    // memory. See `store::Store`.
+    pub catalog_database: Option<PathBuf>,
+
+    // The fraction (from 0 to 1) of images that have to be prefetched after starting before
+    // /readyz and /api/ready report the server as ready.
+    #[serde(default)]
+    pub ready_after_prefetch: f64,
+
+    // Re-index every game this often, to pick up changes on IGDB without a restart.
+    pub reindex_interval_hours: Option<u64>,
+    // Re-index when this config, the games file, or the games in the root folders change.
+    #[serde(default)]
+    pub watch_for_changes: bool,
+    // How long metadata from IGDB is reused before it's fetched again.
+    #[serde(default = "default_metadata_cache_hours")]
+    pub metadata_cache_hours: u64,
+
+    // Timeouts for requests to IGDB and Twitch, in seconds.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    // How many more times a request to IGDB or its image host is tried after it fails in a way
+    // that might go away by itself, like being rate limited.
+    #[serde(default = "default_request_retries")]
+    pub request_retries: u32,
+    // Keep retrying Twitch and IGDB for this many seconds on startup, for when the network
+    // isn't up yet.
+    #[serde(default)]
+    pub startup_grace_period: u64,
+
+    // error, warn, info, or debug. RUST_LOG overrides it.
+    #[serde(default)]
+    pub log_level: log::Level,
+
+    // text or json.
+    #[serde(default)]
+    pub log_format: log::Format,
 
     pub prefetch_threads: Option<usize>, // For performance benchmarking only at the moment.
 }
@@ -79,40 +456,154 @@ impl Config {
             return Err(Error::NotFinishedSettingUp);
         }
 
-        if config.https {
+        if let Some(path) = &config.games_file {
+            #[derive(Deserialize)]
+            struct GamesFile {
+                #[serde(default)]
+                games: Vec<Game>,
+            }
+            let text = fs::read_to_string(path)
+                .map_err(|err| Error::MissingGamesFile(path.clone(), err))?;
+            let games_file: GamesFile =
+                toml::from_str(&text).map_err(|err| Error::BadGamesFile(path.clone(), err))?;
+            // Slugs that are in both files are caught along with any other duplicates below.
+            config.games.extend(games_file.games);
+        }
+
+        // Games that aren't on IGDB need at least a name to go by.
+        if let Some(game) = config
+            .games
+            .iter()
+            .find(|game| game.slug.is_none() && game.name.is_none())
+        {
+            return Err(Error::MissingName(game.path.clone()));
+        }
+
+        let ports = if config.unix_socket.is_some() {
+            Vec::new()
+        } else if config.https {
+            if config.http_port == config.https_port {
+                return Err(Error::BadPort(format!(
+                    "http_port and https_port are both {}, but with https on they need to be \
+                    different: https_port serves the site and http_port redirects to it",
+                    config.http_port
+                )));
+            }
+            // The redirect has to know where to send people, so neither can be left up to the OS.
+            if config.http_port == 0 || config.https_port == 0 {
+                return Err(Error::BadPort(
+                    "http_port and https_port can't be 0 with https on".to_string(),
+                ));
+            }
+            vec![config.http_port, config.https_port]
+        } else {
+            // 0 lets the OS pick a free port, which is printed on startup.
+            vec![config.http_port]
+        };
+        let privileged_ports = ports
+            .into_iter()
+            .filter(|&port| port != 0 && !can_bind(port))
+            .map(Warning::PrivilegedPort)
+            .collect::<Vec<_>>();
+
+        config.twitch_client_id = resolve_secret("twitch_client_id", config.twitch_client_id)?;
+        config.twitch_client_secret =
+            resolve_secret("twitch_client_secret", config.twitch_client_secret)?;
+        config.admin_token = match config.admin_token.take() {
+            Some(token) => Some(resolve_secret("admin_token", token)?),
+            None => std::env::var("GRIFTER_ADMIN_TOKEN").ok(),
+        }
+        .filter(|token| !token.is_empty());
+
+        if let Some(auth) = &config.auth {
+            if auth.users.is_empty() {
+                return Err(Error::BadAuth(
+                    "[auth] is there but doesn't have any users, so nobody could get in"
+                        .to_string(),
+                ));
+            }
+            // Checking a password against a plain password would never match, and it'd be kept
+            // in the config in plain sight.
+            let is_bcrypt = |hash: &str| hash.len() == 60 && hash.starts_with("$2");
+            if let Some(user) = auth.users.iter().find(|u| !is_bcrypt(&u.password_hash)) {
+                return Err(Error::BadAuth(format!(
+                    "the password_hash for {:?} in [auth] isn't a hash, make one with \
+                    `grifter --hash-password`",
+                    user.username
+                )));
+            }
+        }
+
+        if config.https && config.unix_socket.is_none() {
             let is_certificate_ok = fs::File::open(&config.ssl_certificate).is_ok();
             let is_private_key_ok = fs::File::open(&config.ssl_private_key).is_ok();
-            if !is_certificate_ok || !is_private_key_ok {
+            // An empty file opens just fine, but there's nothing in it to start TLS with.
+            let is_empty = |path: &PathBuf| fs::metadata(path).map_or(false, |m| m.len() == 0);
+            let is_certificate_empty = is_empty(&config.ssl_certificate);
+            let is_private_key_empty = is_empty(&config.ssl_private_key);
+            if !is_certificate_ok
+                || !is_private_key_ok
+                || is_certificate_empty
+                || is_private_key_empty
+            {
                 return Err(Error::BadSsl {
                     missing_certificate: !is_certificate_ok,
                     missing_private_key: !is_private_key_ok,
+                    empty_certificate: is_certificate_empty,
+                    empty_private_key: is_private_key_empty,
                 });
             }
         }
 
-        // Check for executables that exist but aren't listed in the config file.
-        let root = fs::read_dir(&config.root).map_err(Error::BadRoot)?;
-        let unused_executables = root
-            .filter_map(|dir_entry| match dir_entry.map(|entry| entry.file_name()) {
-                Ok(file_name) => {
-                    if !config.games.iter().any(|game| game.path == file_name) {
+        // Check for executables that exist but aren't listed in the config file. They're sorted
+        // by name within each root, since the order they're listed in depends on the filesystem.
+        let mut unused_executables = Vec::new();
+        for root in config.root.iter() {
+            let entries = fs::read_dir(root).map_err(|err| Error::BadRoot(root.clone(), err))?;
+            // Entries that can't be read are skipped, there's nothing to tell about them anyway.
+            let mut unused: Vec<_> = entries
+                .filter_map(|dir_entry| {
+                    let file_name = dir_entry.ok()?.file_name();
+                    let is_used = config.games.iter().any(|game| {
+                        game.path == file_name
+                            || game.extras.iter().any(|extra| extra.path == file_name)
+                            || game.dlc.iter().any(|dlc| dlc.path == file_name)
+                    });
+                    if !is_used {
                         Some(file_name)
                     } else {
                         None
                     }
-                }
-                Err(_) => panic!(),
-            })
-            .map(Warning::UnusedExe)
-            .collect();
+                })
+                .collect();
+            unused.sort();
+            unused_executables.extend(unused.into_iter().map(Warning::UnusedExe));
+        }
 
-        // Check for missing executables.
-        let root = &mut config.root;
+        // Check for missing executables. These stay in the config since they might just be
+        // temporarily unavailable (like on a network mount that's down); they're listed in the
+        // catalog as unavailable.
         let missing_games = config
             .games
-            .drain_filter(|g| !root.join(&g.path).exists())
+            .iter()
+            .filter(|g| !config.resolve(&g.path).exists())
+            .cloned()
             .map(Warning::MissingExe)
             .collect::<Vec<_>>();
+        let missing_extras = config
+            .games
+            .iter()
+            .flat_map(|g| g.extras.iter().map(move |extra| (g, extra)))
+            .filter(|(_, extra)| !config.resolve(&extra.path).exists())
+            .map(|(g, extra)| Warning::MissingExtra(g.clone(), extra.clone()))
+            .collect::<Vec<_>>();
+        let missing_dlc = config
+            .games
+            .iter()
+            .flat_map(|g| g.dlc.iter().map(move |dlc| (g, dlc)))
+            .filter(|(_, dlc)| !config.resolve(&dlc.path).exists())
+            .map(|(g, dlc)| Warning::MissingDlc(g.clone(), dlc.clone()))
+            .collect::<Vec<_>>();
 
         // Check for duplicate game entries.
         let conflicting_games = drain_duplicates(&mut config.games)
@@ -120,25 +611,347 @@ impl Config {
             .map(Warning::ConflictingGames)
             .collect::<Vec<_>>();
 
-        let warnings = [unused_executables, conflicting_games, missing_games].concat();
+        let warnings = [
+            privileged_ports,
+            unused_executables,
+            conflicting_games,
+            missing_games,
+            missing_extras,
+            missing_dlc,
+        ]
+        .concat();
         Ok((config, warnings))
     }
+
+    // Where a game or extra is, in the first root that has it. Paths that aren't in any of them
+    // are put in the first root, which is where they'll be reported missing from.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.root
+            .iter()
+            .map(|root| root.join(path))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| self.root[0].join(path))
+    }
+
+    // A few of IGDB's genre names are ugly or verbose, so they're renamed unless the config
+    // renames them itself.
+    pub fn genre_names(&self) -> BTreeMap<String, String> {
+        let mut names: BTreeMap<String, String> = [
+            ("25", "Hack and slash"),
+            ("16", "Turn-based strategy"),
+            ("11", "Real Time Strategy"),
+        ]
+        .iter()
+        .map(|&(genre, name)| (genre.to_string(), name.to_string()))
+        .collect();
+        names.extend(self.genre_overrides.clone());
+        names
+    }
+
+    // The box thumbnails are scaled down to fit inside. Without either set, they're only
+    // limited in height.
+    pub fn thumbnail_max(&self) -> (Option<u32>, Option<u32>) {
+        match (self.thumbnail_max_width, self.thumbnail_max_height) {
+            (None, None) => (None, Some(game::THUMBNAIL_HEIGHT)),
+            max => max,
+        }
+    }
+}
+
+// `root = '/games'` is short for `root = ['/games']`. At least one is needed.
+fn deserialize_roots<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Roots {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    match Roots::deserialize(deserializer)? {
+        Roots::One(root) => Ok(vec![root]),
+        Roots::Many(roots) if roots.is_empty() => {
+            Err(serde::de::Error::custom("root needs at least one folder"))
+        }
+        Roots::Many(roots) => Ok(roots),
+    }
 }
 
+// Accepts a list of styles, or the older table of style names to keywords. The table can't keep
+// the order it was written in, so its styles are checked alphabetically.
+fn deserialize_graphics_styles<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<GraphicsStyle>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Styles {
+        List(Vec<GraphicsStyle>),
+        Table(BTreeMap<String, Vec<u64>>),
+    }
+    match Styles::deserialize(deserializer)? {
+        Styles::List(styles) => Ok(styles),
+        Styles::Table(styles) => Ok(styles
+            .into_iter()
+            .map(|(name, keywords)| GraphicsStyle { name, keywords })
+            .collect()),
+    }
+}
+
+// Accepts an IP address like "0.0.0.0" or "::", or a hostname like "localhost" which is resolved
+// once here, so a typo is reported with the rest of the config instead of when the server starts.
+fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpAddr, D::Error> {
+    let address = String::deserialize(deserializer)?;
+    if let Ok(ip) = address.parse() {
+        return Ok(ip);
+    }
+    let resolved = (address.as_str(), 0)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next());
+    match resolved {
+        Some(addr) => Ok(addr.ip()),
+        None => Err(serde::de::Error::custom(format!(
+            "\"{}\" isn't an IP address or a hostname that could be found, try \"0.0.0.0\" to \
+            listen on every IPv4 address or \"::\" for every IPv6 address",
+            address
+        ))),
+    }
+}
+
+// Whether listening on a port should work. Ports below 1024 are only restricted on Linux (and
+// only unless ip_unprivileged_port_start says otherwise, like it usually does in containers).
+#[cfg(target_os = "linux")]
+fn can_bind(port: u16) -> bool {
+    let read = |path| fs::read_to_string(path).unwrap_or_default();
+    let unprivileged_start = read("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .trim()
+        .parse()
+        .unwrap_or(1024);
+    if port >= unprivileged_start {
+        return true;
+    }
+    // Bit 10 of the effective capabilities is CAP_NET_BIND_SERVICE, which root has too.
+    const CAP_NET_BIND_SERVICE: u64 = 1 << 10;
+    read("/proc/self/status")
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .map_or(true, |caps| caps & CAP_NET_BIND_SERVICE != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn can_bind(_port: u16) -> bool {
+    true
+}
+
+// Secrets don't have to be written into the config, which is awkward when it's checked in or
+// baked into a container image. A value of "${NAME}" is read from the environment variable
+// NAME, and an empty value falls back to GRIFTER_<FIELD> (like GRIFTER_TWITCH_CLIENT_SECRET)
+// if that's set.
+fn resolve_secret(field: &'static str, value: String) -> Result<String, Error> {
+    if let Some(variable) = value
+        .strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        return std::env::var(variable).map_err(|_| Error::MissingEnv {
+            field,
+            variable: variable.to_string(),
+        });
+    }
+    if value.is_empty() {
+        let variable = format!("GRIFTER_{}", field.to_uppercase());
+        return Ok(std::env::var(variable).unwrap_or_default());
+    }
+    Ok(value)
+}
+
+// A range of IP addresses like "192.168.1.0/24" or "fd00::/8". A bare address is a range of one.
+#[derive(Debug, Clone, Copy)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients connecting to a "::" address show up as IPv4-mapped IPv6 addresses.
+        let ip = match ip {
+            IpAddr::V6(v6) => match v6.segments() {
+                [0, 0, 0, 0, 0, 0xffff, ..] => IpAddr::V4(v6.to_ipv4().unwrap()),
+                _ => ip,
+            },
+            IpAddr::V4(_) => ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| format!("\"{}\" doesn't start with an IP address", s))?;
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => match prefix_len.trim().parse() {
+                Ok(prefix_len) if prefix_len <= max_len => prefix_len,
+                _ => {
+                    return Err(format!(
+                        "\"{}\" needs a prefix length from 0 to {} after the \"/\"",
+                        s, max_len
+                    ))
+                }
+            },
+            None => max_len,
+        };
+        Ok(Cidr {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn default_extra_kind() -> String {
+    "Extra".to_string()
+}
+
+fn default_site_title() -> String {
+    "Grifter".to_string()
+}
+
+fn default_search_threshold() -> f64 {
+    0.7
+}
+
+fn default_metadata_cache_hours() -> u64 {
+    24
+}
+
+fn default_medium_max_width() -> u32 {
+    1280
+}
+
+fn default_image_host() -> String {
+    "https://images.igdb.com".to_string()
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    30
+}
+
+fn default_request_retries() -> u32 {
+    3
+}
+
+// Read and write for the owner and group, so a proxy running as another user can be let in by
+// adding it to the group.
+fn default_unix_socket_mode() -> u32 {
+    0o660
+}
+
+fn default_graphics_styles() -> Vec<GraphicsStyle> {
+    let pixel_art_keywords = vec![
+        891,   // pixel
+        1263,  // pixelated
+        1705,  // pixel-art
+        1780,  // pixel-graphics
+        1952,  // pixels
+        16700, // pixelart
+    ];
+    vec![GraphicsStyle {
+        name: "Pixelated".to_string(),
+        keywords: pixel_art_keywords,
+    }]
+}
+
+fn default_store_links() -> Vec<StoreLink> {
+    let stores = [
+        ("Steam", igdb::WEBSITE_STEAM),
+        ("GOG", igdb::WEBSITE_GOG),
+        ("Itch.io", igdb::WEBSITE_ITCH),
+        ("Epic Games", igdb::WEBSITE_EPIC_GAMES),
+        ("Google Play", igdb::WEBSITE_GOOGLE_PLAY),
+        ("Apple iPhone", igdb::WEBSITE_APPLE_PHONE),
+        ("Apple iPad", igdb::WEBSITE_APPLE_PAD),
+    ];
+    stores
+        .iter()
+        .map(|&(name, category)| StoreLink {
+            name: name.to_string(),
+            category,
+        })
+        .collect()
+}
+
+pub const FILENAME: &str = "grifter.toml";
+
 pub const EXAMPLE_CONFIG: &str =
     "# Read through this entire config to get set up. When you're done, set this to true!\n\
     # This config file is written in TOML. You can get familiar with the syntax of TOML here: https://toml.io/\n\
     im_finished_setting_up = false\n\
     \n\
-    # This is the folder containing your games.\n\
+    # This is the folder containing your games. If they're spread across a few folders (like on different\n\
+    # drives), list them all: root = ['/mnt/ssd/games', '/mnt/nas/games']\n\
+    # Each game's path is looked for in every one of them, first to last.\n\
     root = '/path/to/all/my/games'\n\
     \n\
     # Create a new Twitch application and get the client id and secret.\n\
     # Go here to learn how to do that: https://api-docs.igdb.com/#account-creation\n\
     twitch_client_id = '11b084af98ea18caafcae608a9a0e89c' # This is totally fake. Replace it! \n\
     twitch_client_secret = '11b084af98ea18caafcae608a9a0e89c' # This is totally fake. Replace it! \n\
+    # Rather not keep secrets in this file? Leave them empty and set GRIFTER_TWITCH_CLIENT_ID and\n\
+    # GRIFTER_TWITCH_CLIENT_SECRET in the environment, or write '${SOME_VARIABLE}' to read any other variable.\n\
+    \n\
+    # Optionally, read game metadata from a local JSON file instead of IGDB. It uses the same format\n\
+    # as IGDB's api: { \"games\": [...], \"genres\": [...], \"themes\": [...] }\n\
+    # metadata_file = './metadata.json'\n\
     \n\
     # These are optional server settings. You don't have to configure them; the defaults will work just fine.\n\
+    # address is an IP address or a hostname like \"localhost\". \"0.0.0.0\" listens on every IPv4 address,\n\
+    # and \"::\" listens on every IPv6 and IPv4 address.\n\
     address = \"0.0.0.0\"\n\
     http_port = 39090 # default is 80\n\
     https_port = 39091 # default is 443\n\
@@ -146,10 +959,192 @@ pub const EXAMPLE_CONFIG: &str =
     ssl_certificate = './cert.pem'\n\
     ssl_private_key = './privkey.pem'\n\
     \n\
+    # Listen on a Unix socket instead of the address and ports above, for a reverse proxy on the same machine\n\
+    # (like nginx's `proxy_pass http://unix:/run/grifter/grifter.sock;`). https is left to the proxy, and a\n\
+    # socket left behind by a crash is cleaned up on startup. unix_socket_mode is who can connect to it;\n\
+    # the default lets in the owner and group.\n\
+    # unix_socket = '/run/grifter/grifter.sock'\n\
+    # unix_socket_mode = 0o660\n\
+    \n\
+    # Close connections that haven't sent or received anything in this many seconds, so a client that stalls\n\
+    # (or never finishes sending its request) doesn't hold onto the server's threads. Off by default. Keep it\n\
+    # generous: phones on a slow or spotty connection can go quiet for a while in the middle of a download, like\n\
+    # when they switch networks or the screen turns off, and a timeout that's too short cuts their download off.\n\
+    # Browsers can resume it, but not every download manager does. A reload that takes longer than this loses\n\
+    # its response too, though the reload itself still finishes. With this on, at most 32 connections per CPU\n\
+    # are kept open at once and any more are closed right away.\n\
+    # idle_timeout_seconds = 120\n\
+    \n\
+    # Turn this on if Grifter sits behind a reverse proxy like nginx, so links to your site use the\n\
+    # X-Forwarded-Proto and X-Forwarded-Host headers it sends. Leave it off otherwise; anyone can send those.\n\
+    behind_proxy = false\n\
+    # Only let in clients from these IP ranges. Everyone else gets a 403. Leave it empty to let in everyone.\n\
+    # With behind_proxy on, the client's IP comes from the X-Forwarded-For header.\n\
+    # allowed_cidrs = ['192.168.0.0/16', '10.0.0.0/8', '127.0.0.1', '::1']\n\
+    \n\
+    # The name of your library, shown in the browser tab and when it's installed to a home screen.\n\
+    site_title = 'Grifter'\n\
+    # site_description = 'All of my games'\n\
+    # An image (like a .png) to use as the favicon and home screen icon instead of Grifter's. Square works best.\n\
+    # favicon = './favicon.png'\n\
+    \n\
+    # A secret token for admin endpoints, like POST /api/reload which re-indexes your games without a\n\
+    # restart, GET /api/warnings which lists problems found while indexing, or GET /api/cache which lists\n\
+    # the cached images and how much space they take. Send it as an \"authorization: Bearer <token>\" header.\n\
+    # Admin endpoints are off without it. It can also come from GRIFTER_ADMIN_TOKEN in the environment,\n\
+    # or '${SOME_VARIABLE}'.\n\
+    # admin_token = 'make-up-something-long-and-random'\n\
+    \n\
+    # Slugs of games to highlight at the top of the catalog, in this order.\n\
+    featured = []\n\
+    \n\
+    # Games whose slug can't be found on IGDB are left off the site. Turn this on to list them anyway\n\
+    # (with a note about what went wrong) so they're easy to spot and can still be downloaded.\n\
+    show_unresolved = false\n\
+    \n\
+    # IGDB keyword ids to show as tags on games, like \"roguelike\" or \"metroidvania\". IGDB has thousands\n\
+    # of keywords, so only the ones listed here are used. Look them up here: https://api-docs.igdb.com/#keyword\n\
+    tag_keywords = []\n\
+    \n\
+    # Cover and screenshot originals larger than this are scaled down before being cached, to save disk.\n\
+    # Leave these out to keep images at full size.\n\
+    # original_max_width = 1920\n\
+    # original_max_height = 1080\n\
+    \n\
+    # Thumbnails are the smaller images shown in the list of games, 200 pixels tall by default. Make them bigger\n\
+    # for high-DPI screens or smaller for dense grids. Existing thumbnails are remade at the new size on restart.\n\
+    # thumbnail_max_width = 400\n\
+    # thumbnail_max_height = 200\n\
+    \n\
+    # Covers and screenshots on each game's page are scaled down to this width, so a 4K screenshot isn't sent\n\
+    # just to be shown at a fraction of that. The originals are still there for anyone who asks for them.\n\
+    medium_max_width = 1280\n\
+    \n\
+    # Images are downloaded from IGDB's image server. If you mirror it (like behind your own CDN), point this\n\
+    # at the mirror instead. It has to serve the same paths, like <image_host>/igdb/image/upload/t_original/co1wyy.jpg,\n\
+    # and ignore the file extension like IGDB does.\n\
+    image_host = 'https://images.igdb.com'\n\
+    \n\
+    # Some games have dozens of screenshots. Set this to only keep the first few of each game, which makes\n\
+    # the catalog smaller and leaves fewer images to download. Covers are always kept.\n\
+    # max_screenshots = 8\n\
+    \n\
+    # Covers and screenshots that get replaced on IGDB leave their old images in the cache. Turn this on\n\
+    # to delete cached images that no game uses anymore every time your games are indexed.\n\
+    remove_unused_images = false\n\
+    \n\
+    # Limits how much disk space (in bytes) cached images can use. Every few minutes, the images that were\n\
+    # viewed the longest ago are removed until the cache fits, and they're downloaded again the next time\n\
+    # they're asked for. Every image is downloaded on startup, so set this above what your games need or\n\
+    # they'll keep being downloaded and removed. Leave it out for no limit.\n\
+    # cache_max_bytes = 2000000000\n\
+    \n\
+    # How forgiving searching is of typos, from 0 (anything goes) to 1 (no typos allowed).\n\
+    search_threshold = 0.7\n\
+    # For libraries with thousands of games: keep the catalog in this SQLite database and serve /api/search,\n\
+    # /api/catalog and /api/game from it, instead of going through every game in memory and compressing the\n\
+    # whole catalog again whenever a game changes. Grifter has to be built with\n\
+    # `cargo build --release --features sqlite` for this. It's only read on startup.\n\
+    # catalog_database = './cache/catalog.sqlite3'\n\
+    \n\
+    # For health checks (like in Docker or Kubernetes): GET /api/health (or /healthz) always answers once the\n\
+    # server is up, while GET /api/ready (or /readyz) answers 503 until this fraction (from 0 to 1) of images\n\
+    # has been downloaded.\n\
+    ready_after_prefetch = 0.0\n\
+    \n\
+    # Re-index your games every this many hours, to pick up new screenshots and fixes from IGDB without\n\
+    # a restart. Games added to this config in the meantime get picked up too.\n\
+    # reindex_interval_hours = 24\n\
+    \n\
+    # Re-index as soon as this config (or the games_file) is saved, or games are added to or removed from the\n\
+    # root folder, instead of waiting for a restart. A batch of changes only re-indexes once they've settled.\n\
+    # Changes made from another machine to a network drive aren't noticed, so use reindex_interval_hours\n\
+    # or POST /api/reload for those.\n\
+    # watch_for_changes = true\n\
+    \n\
+    # Metadata from IGDB is saved in the cache folder and reused for this many hours, so restarting doesn't\n\
+    # fetch every game again. Set it to 0 to always fetch. Re-indexing only picks up changes on IGDB once\n\
+    # they're this old, and starting with `grifter --force-refresh` skips the saved metadata.\n\
+    metadata_cache_hours = 24\n\
+    \n\
+    # How long to wait (in seconds) on IGDB and Twitch before giving up on a request.\n\
+    connect_timeout = 10\n\
+    read_timeout = 30\n\
+    # Requests to IGDB that fail because of a network blip, rate limiting, or a problem on IGDB's end are\n\
+    # tried again this many times, waiting a little longer each time.\n\
+    request_retries = 3\n\
+    # If Grifter starts before the network is up (like when it's a service started on boot), keep retrying\n\
+    # Twitch and IGDB for this many seconds before giving up.\n\
+    startup_grace_period = 0\n\
+    \n\
+    # How much to print while running. 'info' prints a line for every request with its status and how long\n\
+    # it took, 'debug' also prints one for every image as it's cached, 'warn' only prints problems, and\n\
+    # 'error' only prints the serious ones. The RUST_LOG environment variable overrides this.\n\
+    log_level = 'info'\n\
+    \n\
+    # Set to 'json' to print each line as a JSON object instead, for log collectors.\n\
+    log_format = 'text'\n\
+    \n\
+    # Games with any of these IGDB keywords get that graphics style, otherwise they're \"Smooth\". Styles are\n\
+    # checked in order, so a game with keywords from more than one gets the first. Setting this replaces the\n\
+    # default, so keep \"Pixelated\" if you still want it.\n\
+    # [[graphics_styles]]\n\
+    # name = 'Pixelated'\n\
+    # keywords = [891, 1263, 1705, 1780, 1952, 16700]\n\
+    # [[graphics_styles]]\n\
+    # name = 'Low-poly'\n\
+    # keywords = [...]\n\
+    \n\
+    # Show these names instead of IGDB's for genres, by IGDB genre id or slug. A few are already renamed\n\
+    # (like 'Hack and slash' for 25), and these are added to those. Genres are listed here:\n\
+    # https://api-docs.igdb.com/#genre\n\
+    # [genre_overrides]\n\
+    # 5 = 'FPS'\n\
+    # role-playing-rpg = 'RPG'\n\
+    \n\
+    # IGDB website categories to link to on each game's page, in this order. Setting these replaces the\n\
+    # default stores (Steam, GOG, Itch.io, Epic Games, Google Play, Apple iPhone and Apple iPad), so list\n\
+    # those too if you still want them. Categories are listed here: https://api-docs.igdb.com/#website-enums\n\
+    # [[store_links]]\n\
+    # name = 'Steam'\n\
+    # category = 13\n\
+    # [[store_links]]\n\
+    # name = 'Discord'\n\
+    # category = 18\n\
+    \n\
+    # Store links submitted to IGDB but not verified yet are left out by default. They're usually right,\n\
+    # and some smaller games don't have any others.\n\
+    include_untrusted_links = false\n\
+    \n\
+    # To keep the site private, like for a group of friends, list who can get in below. Browsers ask for a\n\
+    # username and password before showing anything, except the health checks. Passwords are stored as hashes,\n\
+    # made by running `grifter --hash-password`. Changes to this need a restart.\n\
+    # [auth]\n\
+    # users = [\n\
+    #     { username = 'friend', password_hash = '$2b$12$...' },\n\
+    # ]\n\
+    \n\
     # Now, list all of your games below, each beginning with a `[[games]]` and\n\
     # containing both the \"path\" and the \"slug\" for each game.\n\
-    # - \"path\" is the filename of the game, relative to \"root\". It can be nested within a folder.\n\
-    # - \"slug\" is the IGDB id, otherwise known as a slug.\n\
+    # - \"path\" is the filename of the game, relative to \"root\". It can be nested within a folder, or be a\n\
+    #   folder itself, which is zipped up when it's downloaded.\n\
+    # - \"slug\" is the IGDB id, otherwise known as a slug. When a slug is shared by more than one game, use the\n\
+    #   game's number on IGDB instead, without quotes, like: slug = 1234\n\
+    # - \"name\" (optional) is shown instead of IGDB's name for the game.\n\
+    # - \"hidden\" (optional) keeps the game out of the catalog, but it can still be downloaded by direct link.\n\
+    # - \"summary_override\" (optional) is shown instead of IGDB's summary.\n\
+    # - \"summary_append\" (optional) is added to the end of the summary, like 'Patched to run on Windows 11.'\n\
+    # - \"extra_tags\" (optional) are tags of your own, shown along with the ones from \"tag_keywords\".\n\
+    # - \"extras\" (optional) are other files to download with the game, like a manual or a patch. Each has a\n\
+    #   \"path\" (relative to \"root\"), a \"label\", and a \"type\" that extras are grouped by, like this:\n\
+    #   extras = [{ path = 'Cave Story Manual.pdf', label = 'Manual', type = 'Manual' }]\n\
+    # - \"dlc\" (optional) is downloadable content for the game, like expansions, shown apart from the extras.\n\
+    #   Each has a \"path\" (relative to \"root\") and a \"label\", like this:\n\
+    #   dlc = [{ path = 'Diablo 2 Expansion.zip', label = 'Lord of Destruction' }]\n\
+    \n\
+    # With a lot of games, it can be easier to keep them in a file of their own, listed the same way.\n\
+    # They're added to any that are listed here.\n\
+    # games_file = './games.toml'\n\
     \n\
     # Here are three example games:\n\
     [[games]]\n\
@@ -163,13 +1158,22 @@ pub const EXAMPLE_CONFIG: &str =
     [[games]]\n\
     path = 'The Witness.zip'\n\
     slug = 'the-witness'\n\
+    \n\
+    # Games that aren't on IGDB (like homebrew or prototypes) can leave out the slug and be described here\n\
+    # instead. Only the name is required. Genres are IGDB genre ids: https://api-docs.igdb.com/#genre\n\
+    # [[games]]\n\
+    # path = 'My Homebrew.zip'\n\
+    # name = 'My Homebrew'\n\
+    # summary = 'A little game I made.'\n\
+    # cover = './covers/my-homebrew.png'\n\
+    # genres = [31, 32]\n\
     ";
 
 fn drain_duplicates(games: &mut Vec<Game>) -> Vec<Vec<Game>> {
     let mut slugs_by_count: HashMap<String, usize> = HashMap::new();
     for g in games.iter() {
         slugs_by_count
-            .entry(g.slug.clone())
+            .entry(g.slug())
             .and_modify(|c| *c += 1)
             .or_insert(1);
     }
@@ -180,10 +1184,257 @@ fn drain_duplicates(games: &mut Vec<Game>) -> Vec<Vec<Game>> {
             .filter_map(|(slug, count)| if count > 1 { Some(slug) } else { None });
 
     conflicting_slugs
-        .map(|slug| {
-            games
-                .drain_filter(|game| slug == game.slug.as_str())
-                .collect()
-        })
+        .map(|slug| games.drain_filter(|game| slug == game.slug()).collect())
         .collect()
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A config with everything that's required, for games in `root`. The other modules' tests
+    // build their configs with it too.
+    pub(crate) fn config_for(root: &Path, rest: &str) -> String {
+        format!(
+            "
+            im_finished_setting_up = true
+            root = '{}'
+            address = '127.0.0.1'
+            http_port = 39090
+            https_port = 39091
+            ssl_certificate = '{}'
+            ssl_private_key = '{}'
+            {}
+            ",
+            root.display(),
+            root.join("cert.pem").display(),
+            root.join("privkey.pem").display(),
+            rest
+        )
+    }
+
+    fn messages(warnings: &[Warning]) -> Vec<String> {
+        warnings.iter().map(|warning| warning.to_string()).collect()
+    }
+
+    #[test]
+    fn unfinished_setup_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        let config = config_for(root.path(), "https = false").replace(
+            "im_finished_setting_up = true",
+            "im_finished_setting_up = false",
+        );
+        assert!(matches!(
+            Config::from_str(&config),
+            Err(Error::NotFinishedSettingUp)
+        ));
+
+        let config = config_for(root.path(), "https = false");
+        let (config, warnings) = Config::from_str(&config).unwrap();
+        assert!(config.im_finished_setting_up);
+        assert!(messages(&warnings).is_empty());
+    }
+
+    #[test]
+    fn root_has_to_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("games");
+        match Config::from_str(&config_for(&root, "https = false")) {
+            Err(Error::BadRoot(path, err)) => {
+                assert_eq!(path, root);
+                assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+            }
+            Err(err) => panic!("expected bad_root, got {}", err.code()),
+            Ok(_) => panic!("expected bad_root"),
+        }
+    }
+
+    #[test]
+    fn duplicate_slugs_are_left_out() {
+        let root = tempfile::tempdir().unwrap();
+        for file in &["celeste.zip", "celeste-old.zip", "hades.zip"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        let config = config_for(
+            root.path(),
+            "
+            https = false
+            [[games]]
+            path = 'celeste.zip'
+            slug = 'celeste'
+            [[games]]
+            path = 'celeste-old.zip'
+            slug = 'celeste'
+            [[games]]
+            path = 'hades.zip'
+            slug = 'hades--1'
+            ",
+        );
+        let (config, warnings) = Config::from_str(&config).unwrap();
+        assert_eq!(warnings.len(), 1);
+        match &warnings[0] {
+            Warning::ConflictingGames(games) => {
+                let paths: Vec<&Path> = games.iter().map(|game| game.path.as_path()).collect();
+                assert_eq!(
+                    paths,
+                    [Path::new("celeste.zip"), Path::new("celeste-old.zip")]
+                );
+            }
+            warning => panic!("expected conflicting_games, got {}", warning.code()),
+        }
+        let slugs: Vec<String> = config.games.iter().map(Game::slug).collect();
+        assert_eq!(slugs, ["hades--1"]);
+    }
+
+    #[test]
+    fn missing_and_unused_files_are_warned_about() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("celeste.zip"), "").unwrap();
+        fs::write(root.path().join("stray.zip"), "").unwrap();
+        let config = config_for(
+            root.path(),
+            "
+            https = false
+            [[games]]
+            path = 'celeste.zip'
+            slug = 'celeste'
+            [[games]]
+            path = 'hades.zip'
+            slug = 'hades--1'
+            ",
+        );
+        let (config, warnings) = Config::from_str(&config).unwrap();
+        assert_eq!(
+            messages(&warnings),
+            [
+                "\"stray.zip\" exists in root dir but isn't used",
+                "game path \"hades.zip\" doesn't exist",
+            ]
+        );
+        // Missing games are kept, they might only be gone for now.
+        assert_eq!(config.games.len(), 2);
+    }
+
+    #[test]
+    fn ssl_files_have_to_exist() {
+        for &(has_certificate, has_private_key) in
+            &[(false, false), (true, false), (false, true), (true, true)]
+        {
+            let root = tempfile::tempdir().unwrap();
+            if has_certificate {
+                fs::write(root.path().join("cert.pem"), "certificate").unwrap();
+            }
+            if has_private_key {
+                fs::write(root.path().join("privkey.pem"), "private key").unwrap();
+            }
+            let config = config_for(root.path(), "https = true");
+            match Config::from_str(&config) {
+                Err(Error::BadSsl {
+                    missing_certificate,
+                    missing_private_key,
+                    empty_certificate,
+                    empty_private_key,
+                }) => {
+                    assert_eq!(missing_certificate, !has_certificate);
+                    assert_eq!(missing_private_key, !has_private_key);
+                    assert!(!empty_certificate && !empty_private_key);
+                }
+                Err(err) => panic!("expected bad_ssl, got {}", err.code()),
+                Ok((_, warnings)) => {
+                    assert!(has_certificate && has_private_key);
+                    // cert.pem and privkey.pem are in the root too.
+                    assert_eq!(
+                        messages(&warnings),
+                        [
+                            "\"cert.pem\" exists in root dir but isn't used",
+                            "\"privkey.pem\" exists in root dir but isn't used",
+                        ]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ssl_files_cant_be_empty() {
+        for &(is_certificate_empty, is_private_key_empty) in
+            &[(true, false), (false, true), (true, true)]
+        {
+            let root = tempfile::tempdir().unwrap();
+            let contents = |is_empty: bool, text: &'static str| if is_empty { "" } else { text };
+            fs::write(
+                root.path().join("cert.pem"),
+                contents(is_certificate_empty, "certificate"),
+            )
+            .unwrap();
+            fs::write(
+                root.path().join("privkey.pem"),
+                contents(is_private_key_empty, "private key"),
+            )
+            .unwrap();
+            let config = config_for(root.path(), "https = true");
+            match Config::from_str(&config) {
+                Err(Error::BadSsl {
+                    missing_certificate,
+                    missing_private_key,
+                    empty_certificate,
+                    empty_private_key,
+                }) => {
+                    assert!(!missing_certificate && !missing_private_key);
+                    assert_eq!(empty_certificate, is_certificate_empty);
+                    assert_eq!(empty_private_key, is_private_key_empty);
+                }
+                Err(err) => panic!("expected bad_ssl, got {}", err.code()),
+                Ok(_) => panic!("expected bad_ssl, got a config"),
+            }
+        }
+
+        // A file that's missing isn't also called empty.
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("cert.pem"), "").unwrap();
+        let config = config_for(root.path(), "https = true");
+        match Config::from_str(&config) {
+            Err(Error::BadSsl {
+                missing_certificate,
+                missing_private_key,
+                empty_certificate,
+                empty_private_key,
+            }) => {
+                assert!(!missing_certificate && missing_private_key);
+                assert!(empty_certificate && !empty_private_key);
+            }
+            Err(err) => panic!("expected bad_ssl, got {}", err.code()),
+            Ok(_) => panic!("expected bad_ssl, got a config"),
+        }
+    }
+
+    #[test]
+    fn graphics_styles_keep_their_order() {
+        #[derive(Deserialize)]
+        struct Styles {
+            #[serde(deserialize_with = "deserialize_graphics_styles")]
+            graphics_styles: Vec<GraphicsStyle>,
+        }
+        let names = |toml: &str| -> Vec<String> {
+            let styles: Styles = toml::from_str(toml).unwrap();
+            styles.graphics_styles.into_iter().map(|s| s.name).collect()
+        };
+
+        let list = "
+            [[graphics_styles]]
+            name = 'Voxel'
+            keywords = [1]
+            [[graphics_styles]]
+            name = 'Anime'
+            keywords = [2]
+        ";
+        assert_eq!(names(list), ["Voxel", "Anime"]);
+
+        let table = "
+            [graphics_styles]
+            Voxel = [1]
+            Anime = [2]
+        ";
+        assert_eq!(names(table), ["Anime", "Voxel"]);
+    }
+}
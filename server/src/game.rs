@@ -1,10 +1,16 @@
+use crate::checksum::Checksums;
 use crate::config::{self, Config};
 use crate::igdb;
-use crate::twitch;
+use crate::metadata::MetadataProvider;
 use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use unicode_normalization::char::is_combining_mark;
 use unicode_normalization::UnicodeNormalization;
 
 type Error = Box<dyn std::error::Error>;
@@ -13,63 +19,249 @@ type Result<T> = std::result::Result<T, Error>;
 #[derive(Clone)]
 pub enum Warning {
     MissingSlug(String),
+    IgdbFetchFailed(Vec<String>, String),
+    KeywordFetchFailed(String),
+    MissingFeatured(String),
+    UnexpectedGame(String),
+    DuplicateGame(String),
+    // The game's file is there but couldn't be looked at, like when it isn't readable.
+    MetadataFailed(String, String),
+    // The cover of a game without a slug couldn't be read as an image.
+    CoverFailed(String, String),
 }
 
 impl fmt::Display for Warning {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Warning::MissingSlug(slug) => write!(f, "slug \"{}\" doesn't exist on IGDB", slug),
+            Warning::IgdbFetchFailed(slugs, error) => write!(
+                f,
+                "failed to fetch {} games from IGDB ({}): {:?}",
+                slugs.len(),
+                error,
+                slugs
+            ),
+            Warning::KeywordFetchFailed(error) => {
+                write!(f, "failed to fetch tag keywords from IGDB: {}", error)
+            }
+            Warning::MissingFeatured(slug) => {
+                write!(f, "featured slug \"{}\" isn't one of the games", slug)
+            }
+            Warning::UnexpectedGame(slug) => {
+                write!(
+                    f,
+                    "IGDB returned \"{}\", which isn't one of the games",
+                    slug
+                )
+            }
+            Warning::DuplicateGame(slug) => {
+                write!(
+                    f,
+                    "IGDB returned \"{}\" more than once, using the first",
+                    slug
+                )
+            }
+            Warning::MetadataFailed(slug, error) => {
+                write!(f, "couldn't read the file for \"{}\": {}", slug, error)
+            }
+            Warning::CoverFailed(slug, error) => {
+                write!(f, "couldn't read the cover for \"{}\": {}", slug, error)
+            }
         }
     }
 }
 
+// Whether some games are missing because IGDB couldn't be reached, rather than because they
+// don't exist.
+pub fn is_incomplete(warnings: &[Warning]) -> bool {
+    warnings
+        .iter()
+        .any(|warning| matches!(warning, Warning::IgdbFetchFailed(..)))
+}
+
 pub fn games_from_config(
     config: &Config,
-    last_request: &mut std::time::Instant,
+    metadata: &mut dyn MetadataProvider,
 ) -> Result<(Vec<Game>, Vec<Warning>)> {
-    let access_token = twitch::authenticate(&config.twitch_client_id, &config.twitch_client_secret)
-        .unwrap()
-        .access_token;
-
-    let slugs: Vec<&str> = config.games.iter().map(|g| g.slug.as_str()).collect();
-    let igdb_games = igdb::get_games(
-        &config.twitch_client_id,
-        &access_token,
-        last_request,
-        &slugs,
-    )
-    .unwrap();
-
-    let mut games: Vec<Game> = igdb_games
-        .into_iter()
-        .map(|igdb_game| {
-            let g = config
-                .games
-                .iter()
-                .find(|i| i.slug == igdb_game.slug)
-                .unwrap();
-            let metadata = fs::metadata(config.root.join(&g.path)).unwrap();
-            game(igdb_game, g, metadata, config)
-        })
-        .collect();
+    // Games without a slug aren't on IGDB, so there's nothing to fetch for them.
+    let refs: Vec<igdb::GameRef> = config.games.iter().filter_map(|g| g.slug.clone()).collect();
+    let (igdb_games, failed_batches) = metadata.fetch_games(&refs);
+    let (keywords, keyword_warning) = match metadata.fetch_keywords(&config.tag_keywords) {
+        Ok(keywords) => (keywords, None),
+        Err(err) => (
+            Vec::new(),
+            Some(Warning::KeywordFetchFailed(err.to_string())),
+        ),
+    };
+
+    // IGDB doesn't promise to return exactly one game per slug, or to return them in order, so
+    // each game is paired with its config entry by slug.
+    let mut games: Vec<Game> = Vec::new();
+    let mut matched = HashSet::new();
+    let mut unexpected_games = Vec::new();
+    let mut metadata_warnings = Vec::new();
+    for igdb_game in igdb_games {
+        let slug = normalize_slug(&igdb_game.slug);
+        let g = match config.games.iter().find(|i| {
+            i.slug
+                .as_ref()
+                .map_or(false, |r| r.matches(igdb_game.id, &igdb_game.slug))
+        }) {
+            Some(g) => g,
+            None => {
+                unexpected_games.push(Warning::UnexpectedGame(igdb_game.slug));
+                continue;
+            }
+        };
+        if !matched.insert(slug) {
+            unexpected_games.push(Warning::DuplicateGame(igdb_game.slug));
+            continue;
+        }
+        let metadata = file_metadata(g, config, &mut metadata_warnings);
+        games.push(game(igdb_game, g, metadata, &keywords, config));
+    }
 
-    games.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut cover_warnings = Vec::new();
+    for g in config.games.iter().filter(|g| g.slug.is_none()) {
+        let metadata = file_metadata(g, config, &mut metadata_warnings);
+        games.push(manual_game(g, metadata, config, &mut cover_warnings));
+    }
+
+    games.sort_by(|a, b| natural_cmp(&a.name, &b.name));
 
-    let warnings = config
+    let failed_refs: Vec<&igdb::GameRef> = failed_batches.iter().flat_map(|b| &b.games).collect();
+    let mut warnings: Vec<Warning> = config
         .games
         .iter()
         .filter_map(|a| {
-            if games.iter().any(|b| a.slug == b.slug) {
+            let r = a.slug.as_ref()?;
+            let is_found = games.iter().any(|b| r.matches(b.id, &b.slug));
+            let is_failed = failed_refs.contains(&r);
+            if is_found || is_failed {
                 None
             } else {
-                Some(Warning::MissingSlug(a.slug.to_owned()))
+                Some(Warning::MissingSlug(a.slug()))
             }
         })
         .collect();
 
+    if config.show_unresolved {
+        let unresolved: Vec<Game> = config
+            .games
+            .iter()
+            .filter(|a| match &a.slug {
+                Some(r) => !games.iter().any(|b| r.matches(b.id, &b.slug)),
+                None => false,
+            })
+            .map(|g| {
+                let is_failed = g.slug.as_ref().map_or(false, |r| failed_refs.contains(&r));
+                unresolved_game(g, is_failed, config)
+            })
+            .collect();
+        games.extend(unresolved);
+        games.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    }
+
+    // Only the checksums that are already known. The rest are worked out by the server later.
+    let checksums = Checksums::load();
+    for game in games.iter_mut() {
+        game.blake2b = checksums.get(&game.path).map(str::to_string);
+    }
+    warnings.extend(failed_batches.into_iter().map(|batch| {
+        let games = batch.games.iter().map(ToString::to_string).collect();
+        Warning::IgdbFetchFailed(games, batch.error.to_string())
+    }));
+    warnings.extend(unexpected_games);
+    warnings.extend(metadata_warnings);
+    warnings.extend(cover_warnings);
+    warnings.extend(keyword_warning);
+    warnings.extend(
+        config
+            .featured
+            .iter()
+            .filter(|slug| {
+                let slug = normalize_slug(slug);
+                !games.iter().any(|game| normalize_slug(&game.slug) == slug)
+            })
+            .map(|slug| Warning::MissingFeatured(slug.to_owned())),
+    );
+
     Ok((games, warnings))
 }
 
+// IGDB slugs are lowercase, but the ones typed into the config (or occasionally returned by
+// IGDB) can have stray casing or whitespace. Compare slugs through this.
+pub fn normalize_slug(slug: &str) -> String {
+    slug.trim().to_lowercase()
+}
+
+// Boils a name down to lowercase ascii letters, numbers, and single spaces, so that accents,
+// punctuation, and casing don't get in the way of searching.
+pub fn normalize(name: &str) -> String {
+    let is_alphanumeric = |c: &char| "abcdefghijklmnopqrstuvwxyz1234567890 ".contains(*c);
+    name.nfkd()
+        .filter(char::is_ascii)
+        .flat_map(char::to_lowercase)
+        .filter(is_alphanumeric)
+        .fold(String::new(), |mut s, c| {
+            let is_another_space = c == ' ' && s.ends_with(' ');
+            if !is_another_space {
+                s.push(c);
+            }
+            s
+        })
+        .trim()
+        .to_string()
+}
+
+// Orders names the way a file browser would: case and accents are ignored, and runs of digits
+// are compared by value so "Game 2" comes before "Game 10".
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let fold = |s: &str| -> Vec<char> {
+        s.nfkd()
+            .filter(|c| !is_combining_mark(*c))
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let (folded_a, folded_b) = (fold(a), fold(b));
+    let (mut a_rest, mut b_rest) = (&folded_a[..], &folded_b[..]);
+    while let (Some(x), Some(y)) = (a_rest.first(), b_rest.first()) {
+        let ordering = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (a_digits, a_after) = split_digits(a_rest);
+            let (b_digits, b_after) = split_digits(b_rest);
+            a_rest = a_after;
+            b_rest = b_after;
+            a_digits
+                .len()
+                .cmp(&b_digits.len())
+                .then_with(|| a_digits.cmp(b_digits))
+        } else {
+            a_rest = &a_rest[1..];
+            b_rest = &b_rest[1..];
+            x.cmp(y)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a_rest
+        .len()
+        .cmp(&b_rest.len())
+        // Names that only differ by case or accents still need a stable order.
+        .then_with(|| a.cmp(b))
+}
+
+// Splits off the leading run of digits, without its leading zeros.
+fn split_digits(chars: &[char]) -> (&[char], &[char]) {
+    let end = chars
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .unwrap_or(chars.len());
+    let (digits, rest) = chars.split_at(end);
+    let zeros = digits.iter().take_while(|&&c| c == '0').count();
+    (&digits[zeros..], rest)
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub enum Multiplayer {
     None,
@@ -77,28 +269,126 @@ pub enum Multiplayer {
     Limited(u32),
 }
 
-#[derive(Debug, Serialize, Clone)]
-pub enum Graphics {
-    Pixelated,
-    Smooth,
-}
-
+// Clients use the dimensions to reserve space for images before they load. `width` and
+// `height` are the size of the cached original, which can be smaller than IGDB's.
 #[derive(Debug, Serialize, Clone)]
 pub struct Image {
     pub id: String,
     pub width: u32,
     pub height: u32,
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    // The file a local image is served from. IGDB's images are cached instead.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl Image {
+    fn new(
+        description: &igdb::ImageDescription,
+        original_max: (Option<u32>, Option<u32>),
+        thumbnail_max: (Option<u32>, Option<u32>),
+    ) -> Self {
+        let (width, height) = fit_within((description.width, description.height), original_max);
+        let (thumbnail_width, thumbnail_height) = fit_within((width, height), thumbnail_max);
+        Image {
+            id: description.image_id.clone(),
+            width,
+            height,
+            thumbnail_width,
+            thumbnail_height,
+            source: None,
+        }
+    }
+}
+
+// Local images get ids that can't be mistaken for IGDB's, which are never this long.
+pub const LOCAL_IMAGE_PREFIX: &str = "local";
+
+// Describes an image file like IGDB describes its images. The id comes from what's in the file,
+// so a changed image gets a new id and browsers don't hold on to the old one.
+fn local_image(path: &Path) -> Result<igdb::ImageDescription> {
+    use blake2::{Blake2b, Digest};
+
+    let (width, height) = image::image_dimensions(path)?;
+    let hash: String = Blake2b::digest(&fs::read(path)?)
+        .iter()
+        .take(16)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(igdb::ImageDescription {
+        id: 0,
+        image_id: format!("{}{}", LOCAL_IMAGE_PREFIX, hash),
+        width,
+        height,
+    })
+}
+
+// Thumbnails are scaled down to this height unless the config says otherwise.
+pub const THUMBNAIL_HEIGHT: u32 = 200;
+
+// The size an image ends up after being scaled down to fit inside `max`, keeping its aspect
+// ratio. This rounds the same way the image crate does, so it matches what's in the cache.
+pub fn fit_within(dimensions: (u32, u32), max: (Option<u32>, Option<u32>)) -> (u32, u32) {
+    let (width, height) = dimensions;
+    if width == 0 || height == 0 {
+        return dimensions;
+    }
+    let max_width = max.0.map_or(width, |max| u32::min(max, width));
+    let max_height = max.1.map_or(height, |max| u32::min(max, height));
+    let ratio = f64::min(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+    let scale = |n: u32| u32::max((n as f64 * ratio).round() as u32, 1);
+    (scale(width), scale(height))
+}
+
+// A file that goes with a game, like a manual or a patch. Downloaded from
+// /api/download/{slug}/extras/{index}, where the index is its place in the game's extras.
+#[derive(Debug, Serialize, Clone)]
+pub struct Extra {
+    pub label: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub available: bool,
+}
+
+// Downloaded from /api/download/{slug}/dlc/{index}, like extras.
+#[derive(Debug, Serialize, Clone)]
+pub struct Dlc {
+    pub label: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub available: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StoreLink {
+    pub name: String,
+    pub url: String,
+    pub trusted: bool,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Game {
     // INFO
+    pub id: u64, // IGDB's id. Unlike the slug, this never changes.
     pub name: String,
     pub slug: String,
     pub search_names: Vec<String>,
     pub summary: Option<String>,
     pub genres: Vec<u64>,
     pub themes: Vec<u64>,
+    pub tags: Vec<String>,
+    pub first_release_date: Option<i64>, // Unix timestamp of the earliest release anywhere.
+    // Out of 100. The total mixes ratings from users and critics, the aggregated is only critics.
+    pub total_rating: Option<f64>,
+    pub aggregated_rating: Option<f64>,
+    pub developers: Vec<String>,
+    pub publishers: Vec<String>,
 
     // MULTIPLAYER
     pub has_single_player: bool,
@@ -112,9 +402,11 @@ pub struct Game {
     pub cover: Option<Image>,
     pub screenshots: Vec<Image>,
     pub videos: Vec<String>,
-    pub graphics: Graphics,
+    pub graphics: String,
 
     // STORES
+    pub stores: Vec<StoreLink>,
+    // Kept alongside `stores` for clients that look for these stores by name.
     pub steam: Option<String>,
     pub gog: Option<String>,
     pub itch: Option<String>,
@@ -126,15 +418,31 @@ pub struct Game {
     // FILE INFO
     pub path: PathBuf,
     pub size_bytes: u64,
+    pub added_at: Option<u64>, // Unix timestamp of when the file was added to the library.
     pub version: Option<String>,
+    pub available: bool,
+    // Checksum of the file, as `b2sum` prints it. Missing until it's been worked out, which
+    // happens in the background since big games take a while to read.
+    pub blake2b: Option<String>,
+    pub extras: Vec<Extra>,
+    pub dlc: Vec<Dlc>,
+    // Not found on IGDB, so there's nothing but what's in the config. Only listed when
+    // `show_unresolved` is on.
+    pub unresolved: bool,
+    #[serde(skip)]
+    pub hidden: bool,
 }
 
 fn game(
     game: igdb::Game,
     distribution: &config::Game,
-    metadata: fs::Metadata,
+    metadata: Option<fs::Metadata>,
+    tag_keywords: &[igdb::Keyword],
     config: &config::Config,
 ) -> Game {
+    let original_max = (config.original_max_width, config.original_max_height);
+    let thumbnail_max = config.thumbnail_max();
+
     const PLATFORM_WINDOWS: u64 = 6;
     let pc_multiplayer = game
         .multiplayer_modes
@@ -193,23 +501,24 @@ fn game(
         }
     }
 
-    const PIXEL_ART_KEYWORDS: [u64; 6] = [
-        891,   // pixel
-        1263,  // pixelated
-        1705,  // pixel-art
-        1780,  // pixel-graphics
-        1952,  // pixels
-        16700, // pixelart
-    ];
     let keywords = game.keywords;
-    let has_pixel_art_keyword = PIXEL_ART_KEYWORDS
+    let mut tags: Vec<String> = tag_keywords
         .iter()
-        .any(|keyword| keywords.contains(keyword));
-    let graphics = if has_pixel_art_keyword {
-        Graphics::Pixelated
-    } else {
-        Graphics::Smooth
+        .filter(|keyword| keywords.contains(&keyword.id))
+        .map(|keyword| keyword.name.clone())
+        .collect();
+    for tag in distribution.extra_tags.iter() {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+
+    let summary = distribution.summary_override.clone().or(game.summary);
+    let summary = match (summary, &distribution.summary_append) {
+        (Some(summary), Some(append)) => Some(format!("{}\n\n{}", summary, append)),
+        (summary, append) => summary.or_else(|| append.clone()),
     };
+    let graphics = graphics_style(&config.graphics_styles, &keywords);
 
     let search_names = {
         let alternative_names: Vec<String> = game
@@ -217,28 +526,32 @@ fn game(
             .iter()
             .map(|n| n.name.clone())
             .collect();
-        let is_alphanumeric = |c: &char| "abcdefghijklmnopqrstuvwxyz1234567890 ".contains(*c);
         std::iter::once(game.name.clone())
+            .chain(distribution.name.clone())
             .chain(alternative_names)
-            .map(|n| {
-                n.nfkd()
-                    .filter(char::is_ascii)
-                    .flat_map(char::to_lowercase)
-                    .filter(is_alphanumeric)
-                    .fold(String::new(), |mut s, c| {
-                        let is_another_space = c == ' ' && s.ends_with(' ');
-                        if !is_another_space {
-                            s.push(c);
-                        }
-                        s
-                    })
-                    .trim()
-                    .to_string()
-            })
+            .map(|n| normalize(&n))
             .filter(|s| !s.is_empty())
             .collect()
     };
 
+    let websites = &game.websites;
+    let stores = config
+        .store_links
+        .iter()
+        .filter_map(|store| {
+            let site = websites
+                .iter()
+                .filter(|site| site.trusted || config.include_untrusted_links)
+                .filter(|site| site.category == store.category)
+                .max_by_key(|site| site.trusted)?;
+            Some(StoreLink {
+                name: store.name.clone(),
+                url: site.url.clone(),
+                trusted: site.trusted,
+            })
+        })
+        .collect();
+
     let mut steam = None;
     let mut gog = None;
     let mut itch = None;
@@ -246,8 +559,11 @@ fn game(
     let mut google_play = None;
     let mut apple_phone = None;
     let mut apple_pad = None;
-    for site in game.websites {
-        if !site.trusted {
+    // Trusted sites go last so they take precedence over untrusted ones for the same store.
+    let mut websites = game.websites;
+    websites.sort_by_key(|site| site.trusted);
+    for site in websites {
+        if !site.trusted && !config.include_untrusted_links {
             continue;
         }
         match site.category {
@@ -262,24 +578,41 @@ fn game(
         }
     }
 
+    let involved_companies = &game.involved_companies;
+    let companies = |is_involved: fn(&igdb::InvolvedCompany) -> bool| -> Vec<String> {
+        involved_companies
+            .iter()
+            .filter(|involved| is_involved(involved))
+            .map(|involved| involved.company.name.clone())
+            .collect()
+    };
+    let developers = companies(|involved| involved.developer);
+    let publishers = companies(|involved| involved.publisher);
+
     Game {
-        name: game.name,
+        id: game.id,
+        name: distribution.name.clone().unwrap_or(game.name),
         slug: game.slug,
         search_names,
-        cover: game.cover.map(|cover| Image {
-            id: cover.image_id,
-            width: cover.width,
-            height: cover.height,
-        }),
+        cover: game
+            .cover
+            .map(|cover| Image::new(&cover, original_max, thumbnail_max)),
         genres: game.genres,
         themes: game.themes,
+        tags,
+        first_release_date: game.first_release_date,
+        total_rating: game.total_rating,
+        aggregated_rating: game.aggregated_rating,
+        developers,
+        publishers,
         has_coop_campaign,
         has_single_player,
         offline_coop,
         offline_pvp,
         online_coop,
         online_pvp,
-        summary: game.summary,
+        summary,
+        stores,
         steam,
         gog,
         itch,
@@ -300,23 +633,222 @@ fn game(
         screenshots: game
             .screenshots
             .iter()
-            .map(|screenshot| Image {
-                id: screenshot.image_id.clone(),
-                width: screenshot.width,
-                height: screenshot.height,
-            })
+            .take(config.max_screenshots.unwrap_or(usize::MAX))
+            .map(|screenshot| Image::new(screenshot, original_max, thumbnail_max))
             .collect(),
         graphics,
 
-        size_bytes: metadata.len(),
+        size_bytes: metadata
+            .as_ref()
+            .map(|metadata| file_size(&config.resolve(&distribution.path), metadata))
+            .unwrap_or(0),
+        added_at: metadata.as_ref().and_then(added_at),
+        available: metadata.is_some(),
         version: {
             match title_and_version(&distribution.path.to_string_lossy()) {
                 GameName::TitleAndVersion(_, version) => Some(version),
                 _ => None,
             }
         },
-        path: config.root.join(&distribution.path),
+        path: config.resolve(&distribution.path),
+        blake2b: None,
+        extras: distribution
+            .extras
+            .iter()
+            .map(|extra| {
+                let path = config.resolve(&extra.path);
+                let metadata = fs::metadata(&path).ok();
+                Extra {
+                    label: extra.label.clone(),
+                    kind: extra.kind.clone(),
+                    size_bytes: metadata
+                        .as_ref()
+                        .map(|metadata| file_size(&path, metadata))
+                        .unwrap_or(0),
+                    available: metadata.is_some(),
+                    path,
+                }
+            })
+            .collect(),
+        dlc: distribution
+            .dlc
+            .iter()
+            .map(|dlc| {
+                let path = config.resolve(&dlc.path);
+                let metadata = fs::metadata(&path).ok();
+                Dlc {
+                    label: dlc.label.clone(),
+                    size_bytes: metadata
+                        .as_ref()
+                        .map(|metadata| file_size(&path, metadata))
+                        .unwrap_or(0),
+                    available: metadata.is_some(),
+                    path,
+                }
+            })
+            .collect(),
+        unresolved: false,
+        hidden: distribution.hidden,
+    }
+}
+
+// The first style with any of the game's keywords, or "Smooth" when none of them match.
+fn graphics_style(styles: &[config::GraphicsStyle], keywords: &HashSet<u64>) -> String {
+    styles
+        .iter()
+        .find(|style| style.keywords.iter().any(|k| keywords.contains(k)))
+        .map(|style| style.name.clone())
+        .unwrap_or_else(|| "Smooth".to_string())
+}
+
+// A stand-in for a game IGDB couldn't give us, so the mistake shows up on the site instead of
+// the game silently going missing. It can still be downloaded.
+fn unresolved_game(distribution: &config::Game, is_failed: bool, config: &Config) -> Game {
+    let summary = if is_failed {
+        format!(
+            "Grifter couldn't fetch \"{}\" from IGDB. It'll be tried again the next time your \
+            games are indexed.",
+            distribution.slug()
+        )
+    } else {
+        format!(
+            "IGDB doesn't have a game with the slug \"{}\". Check the slug in {}.",
+            distribution.slug(),
+            config::FILENAME
+        )
+    };
+    let stub = igdb::Game {
+        id: 0,
+        slug: distribution.slug(),
+        name: distribution.slug(),
+        alternative_names: Vec::new(),
+        updated_at: 0,
+        summary: Some(summary),
+        cover: None,
+        game_modes: Vec::new(),
+        genres: Vec::new(),
+        themes: Vec::new(),
+        keywords: Default::default(),
+        multiplayer_modes: Vec::new(),
+        websites: Vec::new(),
+        screenshots: Vec::new(),
+        videos: Vec::new(),
+        first_release_date: None,
+        total_rating: None,
+        aggregated_rating: None,
+        involved_companies: Vec::new(),
+    };
+    let metadata = fs::metadata(config.resolve(&distribution.path)).ok();
+    Game {
+        unresolved: true,
+        ..game(stub, distribution, metadata, &[], config)
+    }
+}
+
+// A game that isn't on IGDB, made from what's written about it in the config.
+fn manual_game(
+    distribution: &config::Game,
+    metadata: Option<fs::Metadata>,
+    config: &Config,
+    warnings: &mut Vec<Warning>,
+) -> Game {
+    let slug = distribution.slug();
+    let cover = distribution
+        .cover
+        .as_ref()
+        .and_then(|path| match local_image(path) {
+            Ok(cover) => Some((cover, path)),
+            Err(err) => {
+                warnings.push(Warning::CoverFailed(slug.clone(), err.to_string()));
+                None
+            }
+        });
+    let stub = igdb::Game {
+        id: 0,
+        slug: slug.clone(),
+        name: distribution.name.clone().unwrap_or_else(|| slug.clone()),
+        alternative_names: Vec::new(),
+        updated_at: 0,
+        summary: None,
+        cover: cover.as_ref().map(|(cover, _)| cover.clone()),
+        game_modes: Vec::new(),
+        genres: distribution.genres.clone(),
+        themes: Vec::new(),
+        keywords: Default::default(),
+        multiplayer_modes: Vec::new(),
+        websites: Vec::new(),
+        screenshots: Vec::new(),
+        videos: Vec::new(),
+        first_release_date: None,
+        total_rating: None,
+        aggregated_rating: None,
+        involved_companies: Vec::new(),
+    };
+    let mut game = game(stub, distribution, metadata, &[], config);
+    if let (Some(image), Some((_, path))) = (game.cover.as_mut(), cover) {
+        image.source = Some(path.clone());
+    }
+    game
+}
+
+// Missing files are already warned about with the rest of the config, they're just
+// unavailable. Anything else is worth hearing about.
+fn file_metadata(
+    distribution: &config::Game,
+    config: &Config,
+    warnings: &mut Vec<Warning>,
+) -> Option<fs::Metadata> {
+    match fs::metadata(config.resolve(&distribution.path)) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            warnings.push(Warning::MetadataFailed(
+                distribution.slug(),
+                err.to_string(),
+            ));
+            None
+        }
+    }
+}
+
+// The size of a file, or of everything in a folder for games that come as a folder of files.
+fn file_size(path: &Path, metadata: &fs::Metadata) -> u64 {
+    if !metadata.is_dir() {
+        return metadata.len();
     }
+    files_in(path)
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+// Every file in a folder and the folders inside it, in the same order every time.
+pub fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    entries.sort();
+    let mut files = Vec::new();
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(files_in(&entry));
+        } else {
+            files.push(entry);
+        }
+    }
+    files
+}
+
+// Creation time isn't available on every platform (or filesystem), so fall back to the last
+// modification time.
+fn added_at(metadata: &fs::Metadata) -> Option<u64> {
+    let time = metadata.created().or_else(|_| metadata.modified()).ok()?;
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_secs())
 }
 
 enum GameName {
@@ -338,3 +870,137 @@ fn title_and_version(string: &str) -> GameName {
         None => GameName::Title(title.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::config_for;
+
+    // Hands back the same games no matter what's asked for, like IGDB can.
+    struct Canned(Vec<igdb::Game>);
+
+    impl MetadataProvider for Canned {
+        fn fetch_games(
+            &mut self,
+            _: &[igdb::GameRef],
+        ) -> (Vec<igdb::Game>, Vec<igdb::FailedBatch>) {
+            (self.0.clone(), Vec::new())
+        }
+
+        fn fetch_keywords(
+            &mut self,
+            _: &[u64],
+        ) -> std::result::Result<Vec<igdb::Keyword>, igdb::Error> {
+            Ok(Vec::new())
+        }
+
+        fn fetch_genres(&mut self) -> std::result::Result<Vec<igdb::Genre>, igdb::Error> {
+            Ok(Vec::new())
+        }
+
+        fn fetch_themes(&mut self) -> std::result::Result<Vec<igdb::Theme>, igdb::Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn igdb_game(id: u64, slug: &str, name: &str) -> igdb::Game {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "slug": slug,
+            "name": name,
+            "updated_at": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn igdb_games_are_paired_with_their_config_entry() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("celeste.zip"), "").unwrap();
+        fs::write(root.path().join("hades.zip"), "").unwrap();
+        let config = config_for(
+            root.path(),
+            "
+            https = false
+            [[games]]
+            path = 'celeste.zip'
+            slug = 'celeste'
+            [[games]]
+            path = 'hades.zip'
+            slug = 'hades--1'
+            ",
+        );
+        let (config, _) = Config::from_str(&config).unwrap();
+        // Out of order, with one game twice and one that nobody asked for.
+        let mut igdb = Canned(vec![
+            igdb_game(2, "hades--1", "Hades"),
+            igdb_game(1, "Celeste", "Celeste"),
+            igdb_game(1, "celeste", "Celeste Again"),
+            igdb_game(3, "portal", "Portal"),
+        ]);
+
+        let (games, warnings) = games_from_config(&config, &mut igdb).unwrap();
+        let paired: Vec<(&str, &Path)> = games
+            .iter()
+            .map(|game| (game.name.as_str(), game.path.as_path()))
+            .collect();
+        assert_eq!(
+            paired,
+            [
+                ("Celeste", root.path().join("celeste.zip").as_path()),
+                ("Hades", root.path().join("hades.zip").as_path())
+            ]
+        );
+        let warnings: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            [
+                "IGDB returned \"celeste\" more than once, using the first",
+                "IGDB returned \"portal\", which isn't one of the games",
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_slug_ignores_case_and_whitespace() {
+        assert_eq!(normalize_slug("super-metroid"), "super-metroid");
+        assert_eq!(normalize_slug("  Super-Metroid\n"), "super-metroid");
+        assert_eq!(normalize_slug("HALF-LIFE-2"), "half-life-2");
+    }
+
+    #[test]
+    fn natural_cmp_orders_like_a_file_browser() {
+        let mut names = vec![
+            "Game 10", "game 2", "Game 1", "Ökami", "Okami HD", "Game 02", "Another", "Game",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            ["Another", "Game", "Game 1", "Game 02", "game 2", "Game 10", "Ökami", "Okami HD"]
+        );
+    }
+
+    #[test]
+    fn natural_cmp_is_only_equal_for_identical_names() {
+        assert_eq!(natural_cmp("Portal", "Portal"), Ordering::Equal);
+        assert_ne!(natural_cmp("Portal", "portal"), Ordering::Equal);
+        assert_ne!(natural_cmp("Game 2", "Game 02"), Ordering::Equal);
+        assert_eq!(natural_cmp("Doom 2", "Doom 2016"), Ordering::Less);
+        assert_eq!(natural_cmp("Doom 9", "Doom II"), Ordering::Less);
+    }
+
+    #[test]
+    fn graphics_style_picks_the_first_match() {
+        let style = |name: &str, keywords: &[u64]| config::GraphicsStyle {
+            name: name.to_string(),
+            keywords: keywords.to_vec(),
+        };
+        let styles = [style("Pixelated", &[1, 2]), style("Low-poly", &[2, 3])];
+        let keywords = |ids: &[u64]| ids.iter().copied().collect::<HashSet<u64>>();
+        assert_eq!(graphics_style(&styles, &keywords(&[1])), "Pixelated");
+        assert_eq!(graphics_style(&styles, &keywords(&[3])), "Low-poly");
+        assert_eq!(graphics_style(&styles, &keywords(&[2, 3])), "Pixelated");
+        assert_eq!(graphics_style(&styles, &keywords(&[4])), "Smooth");
+        assert_eq!(graphics_style(&[], &keywords(&[1])), "Smooth");
+    }
+}
@@ -1,15 +1,20 @@
+use crate::game::normalize_slug;
 use image::ImageFormat;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::collections::HashSet;
 use std::io::Read;
-use std::time::{Duration, Instant};
-use ureq::{get, post, Response};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use ureq::{Agent, AgentBuilder, Response};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ImageDescription {
     pub id: u64,
     pub image_id: String,
+    // Zero when IGDB doesn't know.
+    #[serde(default)]
     pub width: u32,
+    #[serde(default)]
     pub height: u32,
 }
 
@@ -32,6 +37,13 @@ pub struct Theme {
     pub slug: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Keyword {
+    pub id: u64,
+    pub name: String,
+    pub slug: String,
+}
+
 // pub const WEBSITE_OFFICIAL: u64 = 1;
 // pub const WEBSITE_WIKIA: u64 = 2;
 // pub const WEBSITE_WIKIPEDIA: u64 = 3;
@@ -57,6 +69,22 @@ pub struct Website {
     pub url: String,
 }
 
+// A company's part in making a game. A company can be neither developer nor publisher, like one
+// that only did a port.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InvolvedCompany {
+    pub company: Company,
+    #[serde(default)]
+    pub developer: bool,
+    #[serde(default)]
+    pub publisher: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Company {
+    pub name: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MultiplayerMode {
     pub id: u64,
@@ -112,109 +140,231 @@ pub struct Game {
     pub screenshots: Vec<ImageDescription>,
     #[serde(default)]
     pub videos: Vec<Video>,
+    // Unix timestamp, negative for games from before 1970.
+    pub first_release_date: Option<i64>,
+    pub total_rating: Option<f64>,
+    pub aggregated_rating: Option<f64>,
+    #[serde(default)]
+    pub involved_companies: Vec<InvolvedCompany>,
 }
 
 #[derive(Debug)]
 pub enum Error {
     Auth(u16, String),
+    // IGDB didn't understand the query, like when a slug has characters it can't handle.
+    Query(String),
+    Request(ureq::Error),
+    Read(std::io::Error),
+    // IGDB answered, but not with what we expected. Usually because they changed a field.
+    Parse(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Auth(code, message) => {
+                write!(f, "authentication failed ({}): {}", code, message)
+            }
+            Error::Query(message) => write!(f, "bad query: {}", message),
+            Error::Request(err) => write!(f, "request failed: {}", err),
+            Error::Read(err) => write!(f, "failed to read response: {}", err),
+            Error::Parse(err) => write!(f, "unexpected response: {}", err),
+        }
+    }
+}
+
+// A batch of games that IGDB couldn't give us.
+#[derive(Debug)]
+pub struct FailedBatch {
+    pub games: Vec<GameRef>,
+    pub error: Error,
+}
+
+// How a game is looked up on IGDB. Usually that's by slug, but the id works for games whose
+// slug is shared with another game or isn't known. In the config, a number is an id and a
+// string is a slug.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GameRef {
+    Id(u64),
+    Slug(String),
+}
+
+impl GameRef {
+    pub fn matches(&self, id: u64, slug: &str) -> bool {
+        match self {
+            GameRef::Id(ref_id) => *ref_id == id,
+            GameRef::Slug(ref_slug) => normalize_slug(ref_slug) == normalize_slug(slug),
+        }
+    }
+
+    fn condition(&self) -> String {
+        match self {
+            GameRef::Id(id) => format!("id = {}", id),
+            GameRef::Slug(slug) => format!("slug = \"{}\"", normalize_slug(slug)),
+        }
+    }
+}
+
+impl std::fmt::Display for GameRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameRef::Id(id) => write!(f, "{}", id),
+            GameRef::Slug(slug) => write!(f, "{}", slug),
+        }
+    }
 }
 
 const IGDB_ENDPOINT: &str = "https://api.igdb.com/v4";
 const IGDB_QUERY_LIMIT: usize = 500; // Explained at https://api-docs.igdb.com/#pagination
 const IGDB_REQUEST_COOLDOWN: u64 = 250; // Explained at https://api-docs.igdb.com/#rate-limits
 
-pub fn get_games<T>(
-    client_id: &str,
-    access_token: &str,
-    last_request: &mut Instant,
-    slugs: &[T],
-) -> Result<Vec<Game>, Error>
-where
-    T: std::fmt::Display,
-{
-    let mut requests = 0;
-    let mut games: Vec<Game> = Vec::with_capacity(slugs.len());
-    while requests * IGDB_QUERY_LIMIT < slugs.len() {
-        let start = requests * IGDB_QUERY_LIMIT;
-        let end = usize::min((requests + 1) * IGDB_QUERY_LIMIT, slugs.len());
-        let conditions = slugs[start..end]
-            .iter()
-            .map(|s| format!("slug = \"{}\"", &s))
-            .collect::<Vec<String>>()
-            .join(" | ");
-        let fields = [
-            "id",
-            "slug",
-            "name",
-            "updated_at",
-            "cover.*",
-            "videos.video_id",
-            "screenshots.*",
-            "summary",
-            "multiplayer_modes.*",
-            "game_modes",
-            "genres",
-            "themes",
-            "keywords",
-            "alternative_names.name",
-            "websites.category",
-            "websites.trusted",
-            "websites.url",
-        ];
-        let query = format!(
-            "fields {fields}; where {conditions}; limit {limit};",
-            fields = fields.join(", "),
-            conditions = conditions,
-            limit = IGDB_QUERY_LIMIT
-        );
-        sleep_for_cooldown(last_request);
-        let response = post(&format!("{}/games", IGDB_ENDPOINT))
-            .set("client-id", client_id)
-            .set("authorization", &format!("Bearer {}", access_token))
-            .send_string(&query)
-            .unwrap();
-
-        *last_request = Instant::now();
-        let mut queried_games: Vec<Game> = handle_response(response)?;
-        games.append(&mut queried_games);
-        requests += 1;
-    }
+// Every request to IGDB, Twitch, and IGDB's image host goes through one of these (clones share
+// the same connection pool), so connections get reused instead of paying for a new TLS
+// handshake on every request. The timeouts make a hung connection fail with an error instead
+// of stalling startup forever.
+pub fn agent(connect_timeout: Duration, read_timeout: Duration, max_connections: usize) -> Agent {
+    AgentBuilder::new()
+        .timeout_connect(connect_timeout)
+        .timeout_read(read_timeout)
+        .max_idle_connections_per_host(max_connections)
+        .build()
+}
 
-    Ok(games)
+pub struct Client {
+    agent: Agent,
+    endpoint: String,
+    client_id: String,
+    access_token: String,
+    retries: u32,
+    last_request: Instant,
 }
 
-pub fn get_genres(
-    client_id: &str,
-    access_token: &str,
-    last_request: &mut Instant,
-) -> Result<Vec<Genre>, Error> {
-    sleep_for_cooldown(last_request);
-    let query = format!("fields id, name, slug; limit {};", IGDB_QUERY_LIMIT);
-    let response = post(&format!("{}/genres", IGDB_ENDPOINT))
-        .set("client-id", client_id)
-        .set("authorization", &format!("Bearer {}", access_token))
-        .send_string(&query)
-        .unwrap();
+impl Client {
+    pub fn new(agent: Agent, client_id: &str, access_token: &str, retries: u32) -> Self {
+        Self::with_endpoint(agent, IGDB_ENDPOINT, client_id, access_token, retries)
+    }
 
-    *last_request = Instant::now();
-    handle_response(response)
-}
+    // Lets the client talk to something other than the real IGDB, like a local mock server.
+    pub fn with_endpoint(
+        agent: Agent,
+        endpoint: &str,
+        client_id: &str,
+        access_token: &str,
+        retries: u32,
+    ) -> Self {
+        Client {
+            agent,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            client_id: client_id.to_string(),
+            access_token: access_token.to_string(),
+            retries,
+            last_request: Instant::now(),
+        }
+    }
+
+    pub fn get_games(&mut self, refs: &[GameRef]) -> (Vec<Game>, Vec<FailedBatch>) {
+        let mut requests = 0;
+        let mut games: Vec<Game> = Vec::with_capacity(refs.len());
+        let mut failed = Vec::new();
+        while requests * IGDB_QUERY_LIMIT < refs.len() {
+            let start = requests * IGDB_QUERY_LIMIT;
+            let end = usize::min((requests + 1) * IGDB_QUERY_LIMIT, refs.len());
+            let conditions = refs[start..end]
+                .iter()
+                .map(GameRef::condition)
+                .collect::<Vec<String>>()
+                .join(" | ");
+            let fields = [
+                "id",
+                "slug",
+                "name",
+                "updated_at",
+                "cover.*",
+                "videos.video_id",
+                "screenshots.*",
+                "summary",
+                "multiplayer_modes.*",
+                "game_modes",
+                "genres",
+                "themes",
+                "keywords",
+                "alternative_names.name",
+                "websites.category",
+                "websites.trusted",
+                "websites.url",
+                "first_release_date",
+                "total_rating",
+                "aggregated_rating",
+                "involved_companies.company.name",
+                "involved_companies.developer",
+                "involved_companies.publisher",
+            ];
+            let query = format!(
+                "fields {fields}; where {conditions}; limit {limit};",
+                fields = fields.join(", "),
+                conditions = conditions,
+                limit = IGDB_QUERY_LIMIT
+            );
+            match self.post("games", &query) {
+                Ok(mut queried_games) => games.append(&mut queried_games),
+                Err(error) => failed.push(FailedBatch {
+                    games: refs[start..end].to_vec(),
+                    error,
+                }),
+            }
+            requests += 1;
+        }
+
+        (games, failed)
+    }
+
+    pub fn get_genres(&mut self) -> Result<Vec<Genre>, Error> {
+        let query = format!("fields id, name, slug; limit {};", IGDB_QUERY_LIMIT);
+        self.post("genres", &query)
+    }
 
-pub fn get_themes(
-    client_id: &str,
-    access_token: &str,
-    last_request: &mut Instant,
-) -> Result<Vec<Theme>, Error> {
-    sleep_for_cooldown(last_request);
-    let query = format!("fields id, name, slug; limit {};", IGDB_QUERY_LIMIT);
-    let response = post(&format!("{}/themes", IGDB_ENDPOINT))
-        .set("client-id", client_id)
-        .set("authorization", &format!("Bearer {}", access_token))
-        .send_string(&query)
-        .unwrap();
+    pub fn get_themes(&mut self) -> Result<Vec<Theme>, Error> {
+        let query = format!("fields id, name, slug; limit {};", IGDB_QUERY_LIMIT);
+        self.post("themes", &query)
+    }
+
+    pub fn get_keywords(&mut self, ids: &[u64]) -> Result<Vec<Keyword>, Error> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids: Vec<String> = ids.iter().map(u64::to_string).collect();
+        let query = format!(
+            "fields id, name, slug; where id = ({}); limit {};",
+            ids.join(", "),
+            IGDB_QUERY_LIMIT
+        );
+        self.post("keywords", &query)
+    }
 
-    *last_request = Instant::now();
-    handle_response(response)
+    fn post<T>(&mut self, resource: &str, query: &str) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let url = format!("{}/{}", self.endpoint, resource);
+        let response = request_with_retry(self.retries, || {
+            sleep_for_cooldown(&self.last_request);
+            let response = self
+                .agent
+                .post(&url)
+                .set("client-id", &self.client_id)
+                .set("authorization", &format!("Bearer {}", self.access_token))
+                .send_string(query);
+            self.last_request = Instant::now();
+            response
+        });
+
+        let result = response_or_error(response).and_then(handle_response);
+        if let Err(Error::Parse(_)) = result {
+            warn!("The query to /{} was: {}", resource, query);
+        }
+        result
+    }
 }
 
 fn sleep_for_cooldown(last_request: &Instant) {
@@ -225,6 +375,46 @@ fn sleep_for_cooldown(last_request: &Instant) {
     }
 }
 
+// Sends a request again when it fails in a way that's likely to sort itself out: the connection
+// dropping, being rate limited (429), or a problem on the server's end (5xx). The wait doubles
+// after each try, unless the server says how long to wait with retry-after. The jitter keeps the
+// prefetch workers from all coming back at the same moment.
+fn request_with_retry(
+    retries: u32,
+    mut send: impl FnMut() -> Result<Response, ureq::Error>,
+) -> Result<Response, ureq::Error> {
+    let mut delay = Duration::from_millis(500);
+    for _ in 0..retries {
+        let wait = match send() {
+            Err(ureq::Error::Status(status, response)) if status == 429 || status >= 500 => {
+                retry_after(&response).unwrap_or(delay)
+            }
+            Err(ureq::Error::Transport(_)) => delay,
+            result => return result,
+        };
+        std::thread::sleep(wait + jitter(wait / 4));
+        delay *= 2;
+    }
+    send()
+}
+
+// Only the number of seconds, IGDB doesn't send dates. Capped so a bad header can't stall
+// everything.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds: u64 = response.header("retry-after")?.trim().parse().ok()?;
+    Some(Duration::from_secs(u64::min(seconds, 60)))
+}
+
+// Somewhere between nothing and `max`. It only has to differ between threads, so the clock is
+// random enough.
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.subsec_nanos());
+    let max_millis = max.as_millis() as u64;
+    Duration::from_millis(u64::from(nanos) % (max_millis + 1))
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct IgdbAuthError {
@@ -238,12 +428,22 @@ struct IgdbQueryError {
     pub cause: String,
 }
 
+// ureq treats 4xx and 5xx statuses as errors, but `handle_response` wants to see those
+// responses so it can pull IGDB's error message out of them.
+fn response_or_error(response: Result<Response, ureq::Error>) -> Result<Response, Error> {
+    match response {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(_, response)) => Ok(response),
+        Err(err) => Err(Error::Request(err)),
+    }
+}
+
 fn handle_response<T>(response: Response) -> Result<T, Error>
 where
     T: DeserializeOwned,
 {
     let code = response.status();
-    let body = response.into_string().unwrap();
+    let body = response.into_string().map_err(Error::Read)?;
 
     if code == 401 || code == 403 {
         let error = serde_json::from_str::<IgdbAuthError>(&body);
@@ -253,20 +453,35 @@ where
         };
         Err(Error::Auth(code, message))
     } else if code == 400 {
-        // 400 from IGDB means there's syntax errors in the query. We shouldn't try to
-        // gracefully handle this. The syntax errors should just be fixed as soon as possible.
-        let errors: Vec<IgdbQueryError> = serde_json::from_str(&body).unwrap();
-        panic!("{:?}", errors);
+        // 400 from IGDB means there's syntax errors in the query. Usually that's a bug here, but
+        // it can also be a slug with characters that don't belong in one, and that shouldn't
+        // take down the rest of the games.
+        let message = match serde_json::from_str::<Vec<IgdbQueryError>>(&body) {
+            Ok(errors) => errors
+                .iter()
+                .map(|error| format!("{} ({})", error.title, error.cause))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Err(_) => body,
+        };
+        Err(Error::Query(message))
     } else {
         match serde_json::from_str::<T>(&body) {
             Ok(data) => Ok(data),
             Err(err) => {
-                println!("{}", err);
-                println!();
-                for line in body.lines().skip(err.line()).take(10) {
-                    println!("{}", line);
-                }
-                panic!()
+                // Leave it to the caller to skip whatever this was for, and show enough of the
+                // response to tell what changed.
+                let excerpt: Vec<&str> = body
+                    .lines()
+                    .skip(err.line().saturating_sub(1))
+                    .take(10)
+                    .collect();
+                warn!(
+                    "Couldn't parse a response from IGDB: {}\n\n{}",
+                    err,
+                    excerpt.join("\n")
+                );
+                Err(Error::Parse(err))
             }
         }
     }
@@ -283,14 +498,43 @@ pub enum ImageError {
     MissingFormat,
     BadResponse(ureq::Error),
     BadRead(std::io::Error),
+    BadId(String),
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::UnsupportedFormat(mime) => write!(f, "unsupported image format {}", mime),
+            ImageError::MissingFormat => write!(f, "response didn't have a content-type"),
+            ImageError::BadResponse(err) => write!(f, "request failed: {}", err),
+            ImageError::BadRead(err) => write!(f, "failed to read response: {}", err),
+            ImageError::BadId(id) => write!(f, "{:?} isn't an IGDB image id", id),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+// IGDB image ids are short strings of lowercase letters and numbers, like "co1wyy".
+pub fn is_valid_image_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
 }
 
-pub fn get_image(id: &str) -> Result<Image, ImageError> {
+pub fn get_image(agent: &Agent, host: &str, id: &str, retries: u32) -> Result<Image, ImageError> {
+    if !is_valid_image_id(id) {
+        return Err(ImageError::BadId(id.to_string()));
+    }
     let url = format!(
-        "https://images.igdb.com/igdb/image/upload/t_original/{}.foobar", // IGDB ignores the extension; we can request anything.
+        "{}/igdb/image/upload/t_original/{}.foobar", // IGDB ignores the extension; we can request anything.
+        host.trim_end_matches('/'),
         id
     );
-    let response = get(&url).call().map_err(ImageError::BadResponse)?;
+    let response =
+        request_with_retry(retries, || agent.get(&url).call()).map_err(ImageError::BadResponse)?;
     let format = match response.header("content-type") {
         Some("image/jpeg") => ImageFormat::Jpeg,
         Some("image/png") => ImageFormat::Png,
@@ -311,3 +555,186 @@ pub fn get_image(id: &str) -> Result<Image, ImageError> {
         format,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+
+    fn client(server: &MockServer) -> Client {
+        client_with_retries(server, 0)
+    }
+
+    fn client_with_retries(server: &MockServer, retries: u32) -> Client {
+        let agent = agent(Duration::from_secs(5), Duration::from_secs(5), 1);
+        // The trailing slash is trimmed off.
+        Client::with_endpoint(agent, &format!("{}/", server.url), "id", "token", retries)
+    }
+
+    const GENRES: &str =
+        r#"[{"id": 12, "name": "Role-playing (RPG)", "slug": "role-playing-rpg"}]"#;
+
+    #[test]
+    fn client_posts_queries_to_the_endpoint() {
+        let server = MockServer::start(vec![(200, GENRES)]);
+        let genres = client(&server).get_genres().unwrap();
+        assert_eq!(genres.len(), 1);
+        assert_eq!(genres[0].id, 12);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/genres");
+        assert_eq!(requests[0].header("client-id"), Some("id"));
+        assert_eq!(requests[0].header("authorization"), Some("Bearer token"));
+        assert_eq!(requests[0].body, "fields id, name, slug; limit 500;");
+    }
+
+    #[test]
+    fn client_reports_syntax_errors() {
+        let server = MockServer::start(vec![(
+            400,
+            r#"[{"title": "Syntax Error", "status": 400, "cause": "Expecting a STRING"}]"#,
+        )]);
+        match client(&server).get_genres() {
+            Err(Error::Query(message)) => assert_eq!(message, "Syntax Error (Expecting a STRING)"),
+            result => panic!("expected a query error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn client_reports_failed_authentication() {
+        let server = MockServer::start(vec![(401, r#"{"Message": "Authorization Failure"}"#)]);
+        match client(&server).get_genres() {
+            Err(Error::Auth(401, message)) => assert_eq!(message, "Authorization Failure"),
+            result => panic!("expected an auth error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn client_pages_through_games() {
+        let page = |id: u64| {
+            format!(
+                r#"[{{"id": {}, "slug": "game-{}", "name": "Game {}", "updated_at": 0}}]"#,
+                id, id, id
+            )
+        };
+        let (first, second) = (page(1), page(501));
+        let server = MockServer::start(vec![(200, &first), (200, &second)]);
+        let refs: Vec<GameRef> = (1..=501).map(GameRef::Id).collect();
+        let (games, failed) = client(&server).get_games(&refs);
+        assert!(failed.is_empty());
+        assert_eq!(games.iter().map(|g| g.id).collect::<Vec<_>>(), [1, 501]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].body.contains("where id = 1 | id = 2 |"));
+        assert!(requests[0].body.contains("id = 500;"));
+        assert!(requests[1].body.contains("where id = 501;"));
+    }
+
+    #[test]
+    fn client_retries_when_rate_limited_or_the_server_fails() {
+        let server = MockServer::start(vec![(429, "{}"), (503, "{}"), (200, GENRES)]);
+        let genres = client_with_retries(&server, 2).get_genres().unwrap();
+        assert_eq!(genres[0].id, 12);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn client_gives_up_after_its_retries() {
+        let server = MockServer::start(vec![(503, "{}"), (503, "{}"), (200, GENRES)]);
+        assert!(client_with_retries(&server, 1).get_genres().is_err());
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn client_doesnt_retry_other_client_errors() {
+        let server = MockServer::start(vec![
+            (
+                400,
+                r#"[{"title": "Syntax Error", "status": 400, "cause": "Expecting a STRING"}]"#,
+            ),
+            (200, GENRES),
+        ]);
+        assert!(matches!(
+            client_with_retries(&server, 2).get_genres(),
+            Err(Error::Query(_))
+        ));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn client_waits_as_long_as_retry_after_says() {
+        let server = MockServer::start_with_headers(vec![
+            (429, &[("retry-after", "1")], "{}"),
+            (200, &[], GENRES),
+        ]);
+        let started = Instant::now();
+        client_with_retries(&server, 1).get_genres().unwrap();
+        // Without the header, the first wait is only half a second and a bit.
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let response = |retry_after: &str| -> Response {
+            format!(
+                "HTTP/1.1 429 Too Many Requests\r\nretry-after: {}\r\n\r\n",
+                retry_after
+            )
+            .parse()
+            .unwrap()
+        };
+        assert_eq!(retry_after(&response("5")), Some(Duration::from_secs(5)));
+        assert_eq!(
+            retry_after(&response("3600")),
+            Some(Duration::from_secs(60))
+        );
+        // Dates aren't read.
+        assert_eq!(
+            retry_after(&response("Wed, 21 Oct 2015 07:28:00 GMT")),
+            None
+        );
+    }
+
+    #[test]
+    fn game_ref_matches_slugs_loosely() {
+        let slug: GameRef = serde_json::from_str("\" Super-Metroid \"").unwrap();
+        assert!(slug.matches(1, "super-metroid"));
+        assert!(slug.matches(1, "SUPER-METROID"));
+        assert!(!slug.matches(1, "super-metroid-2"));
+        assert_eq!(slug.condition(), "slug = \"super-metroid\"");
+
+        let id: GameRef = serde_json::from_str("1103").unwrap();
+        assert!(id.matches(1103, "anything"));
+        assert!(!id.matches(1104, "super-metroid"));
+        assert_eq!(id.condition(), "id = 1103");
+    }
+
+    #[test]
+    fn image_ids_are_lowercase_letters_and_numbers() {
+        assert!(is_valid_image_id("co1wyy"));
+        assert!(is_valid_image_id("sc6kgp"));
+        assert!(is_valid_image_id("123"));
+        assert!(is_valid_image_id(&"a".repeat(64)));
+
+        assert!(!is_valid_image_id(""));
+        assert!(!is_valid_image_id(&"a".repeat(65)));
+        assert!(!is_valid_image_id("Co1wyy"));
+        assert!(!is_valid_image_id("co1wyy.jpg"));
+        assert!(!is_valid_image_id("../co1wyy"));
+        assert!(!is_valid_image_id("co1wyy?x=1"));
+        assert!(!is_valid_image_id("co1 wyy"));
+        assert!(!is_valid_image_id("co1wyé"));
+    }
+
+    #[test]
+    fn bad_image_ids_are_never_requested() {
+        let server = MockServer::start(vec![]);
+        let agent = agent(Duration::from_secs(5), Duration::from_secs(5), 1);
+        let result = get_image(&agent, &server.url, "../../etc/passwd", 0);
+        assert!(matches!(result, Err(ImageError::BadId(id)) if id == "../../etc/passwd"));
+        assert!(server.requests().is_empty());
+    }
+}
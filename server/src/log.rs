@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+// How much the server prints while it's running, from only errors up to a line for every request
+// (info) and every prefetched image (debug).
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+// Text is for reading in a terminal. JSON prints one object per line for log collectors, with
+// the time, level, and message, plus any fields the line has (like a request's status).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+// Sends everything from tracing's `info!`, `warn!`, and so on to stdout. RUST_LOG takes
+// precedence over the config, per-module filters and all, like it does for most rust programs.
+pub fn init(level: Level, format: Format) {
+    let level = match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false);
+    match format {
+        Format::Text => subscriber.init(),
+        Format::Json => subscriber.json().flatten_event(true).init(),
+    }
+}
@@ -3,16 +3,42 @@
 #![feature(drain_filter)]
 
 use config::Config;
+use metadata::MetadataProvider;
+use std::fmt::Display;
 use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
 mod api;
+mod checksum;
 mod client_web;
 mod config;
 mod game;
 mod igdb;
+mod log;
+mod metadata;
+#[cfg(test)]
+mod mock;
+mod relay;
+mod search;
+mod shutdown;
+mod store;
 mod twitch;
+mod zip;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--config-check") {
+        let as_json = args.iter().any(|arg| arg == "--json");
+        std::process::exit(check_config(as_json));
+    }
+    if args.iter().any(|arg| arg == "--hash-password") {
+        std::process::exit(hash_password());
+    }
+
+    let force_refresh = args.iter().any(|arg| arg == "--force-refresh");
+
     const VERSION: &str = env!("CARGO_PKG_VERSION_MINOR");
     println!("         _ ___ _           ");
     println!(" ___ ___|_|  _| |_ ___ ___ ");
@@ -21,7 +47,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("|___|{:>20}", format!("version {}", VERSION));
     println!();
 
-    let config_filename = "grifter.toml";
+    let config_filename = config::FILENAME;
     let config_text = match fs::read_to_string(config_filename) {
         Ok(text) => text,
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -33,16 +59,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         Err(err) => return Err(Box::new(err)),
     };
-    let config = match Config::from_str(&config_text) {
+    let (config, config_warnings) = match Config::from_str(&config_text) {
         Ok((config, warnings)) => {
-            for warning in warnings {
+            for warning in warnings.iter() {
                 println!("Warning: {}", warning);
             }
-            config
+            (config, warnings)
         }
-        Err(crate::config::Error::BadRoot(_)) => {
+        Err(crate::config::Error::BadRoot(root, _)) => {
             println!(
-                "There was a problem. The \"root\" folder specified in your config doesn't exist."
+                "There was a problem. The \"root\" folder {:?} specified in your config doesn't exist.",
+                root
             );
             return Ok(());
         }
@@ -53,6 +80,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("The toml docs are really helpful, check them out: https://toml.io/");
             return Ok(());
         }
+        Err(crate::config::Error::MissingGamesFile(path, err)) => {
+            println!(
+                "There was a problem. The games_file {:?} couldn't be read.",
+                path
+            );
+            println!("  {}", err);
+            return Ok(());
+        }
+        Err(crate::config::Error::BadGamesFile(path, err)) => {
+            println!("There was a problem. The games_file couldn't be parsed.");
+            println!("  {}: {}", path.display(), err);
+            println!();
+            println!("The toml docs are really helpful, check them out: https://toml.io/");
+            return Ok(());
+        }
         Err(crate::config::Error::NotFinishedSettingUp) => {
             println!(
                 "The server can't be started until you're finished configuring \"grifter.toml\"."
@@ -65,12 +107,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(crate::config::Error::BadSsl {
             missing_certificate,
             missing_private_key,
+            empty_certificate,
+            empty_private_key,
         }) => {
-            println!("You have SSL enabled in \"grifter.toml\" but some files are missing:");
+            println!(
+                "You have SSL enabled in \"grifter.toml\" but some files are missing or empty:"
+            );
             println!(
                 "  Certificate: {}",
                 if missing_certificate {
                     "NOT FOUND"
+                } else if empty_certificate {
+                    "EMPTY"
                 } else {
                     "Found! This one's ok."
                 }
@@ -79,6 +127,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "  Private Key: {}",
                 if missing_private_key {
                     "NOT FOUND"
+                } else if empty_private_key {
+                    "EMPTY"
                 } else {
                     "Found! This one's ok."
                 }
@@ -86,32 +136,240 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Either disable https, or fix the missing files.");
             return Ok(());
         }
+        Err(err @ crate::config::Error::BadPort(_))
+        | Err(err @ crate::config::Error::MissingEnv { .. })
+        | Err(err @ crate::config::Error::MissingName(_))
+        | Err(err @ crate::config::Error::BadAuth(_)) => {
+            println!("There was a problem. {}.", err);
+            return Ok(());
+        }
     };
 
-    let mut last_request = std::time::Instant::now();
-    let (games, warnings) = game::games_from_config(&config, &mut last_request)?;
-    for warning in warnings {
-        println!("Warning: {}", warning);
-    }
-    println!("Indexed {} games.", games.len());
+    log::init(config.log_level, config.log_format);
 
-    let (sender, receiver) = crossbeam_channel::unbounded();
     let prefetch_threads = config
         .prefetch_threads
         .map(|threads| num_cpus::get() * threads)
         .unwrap_or_else(num_cpus::get);
-    std::thread::spawn(move || {
-        api::image_prefetch_pool(prefetch_threads, receiver);
-    });
-    for game in &games {
-        for screenshot in &game.screenshots {
-            sender.send(screenshot.id.clone()).unwrap();
+    let agent = igdb::agent(
+        Duration::from_secs(config.connect_timeout),
+        Duration::from_secs(config.read_timeout),
+        prefetch_threads,
+    );
+
+    let mut retry = StartupRetry::new(Duration::from_secs(config.startup_grace_period));
+    let mut metadata: Box<dyn MetadataProvider + Send> = match &config.metadata_file {
+        Some(path) => Box::new(metadata::FileProvider::open(path)?),
+        None => {
+            let auth = loop {
+                let auth = twitch::authenticate(
+                    &agent,
+                    &config.twitch_client_id,
+                    &config.twitch_client_secret,
+                );
+                match auth {
+                    // Only a failed connection is worth retrying, a rejected login stays rejected.
+                    Err(twitch::Error::Request(err))
+                        if retry.wait("Authenticating with Twitch", &err) =>
+                    {
+                        continue
+                    }
+                    auth => break auth,
+                }
+            };
+            let access_token = match auth {
+                Ok(auth) => auth.access_token,
+                Err(err) => {
+                    error!("There was a problem authenticating with Twitch: {}", err);
+                    return Ok(());
+                }
+            };
+            let client = igdb::Client::new(
+                agent.clone(),
+                &config.twitch_client_id,
+                &access_token,
+                config.request_retries,
+            );
+            Box::new(metadata::CachedProvider::open(
+                client,
+                Duration::from_secs(config.metadata_cache_hours * 60 * 60),
+                force_refresh,
+            ))
         }
-        if let Some(cover) = &game.cover {
-            sender.send(cover.id.clone()).unwrap();
+    };
+    let (games, warnings) = loop {
+        let (games, warnings) = game::games_from_config(&config, metadata.as_mut())?;
+        let failure = warnings
+            .iter()
+            .find(|warning| matches!(warning, game::Warning::IgdbFetchFailed(..)));
+        match failure {
+            Some(failure) if retry.wait("Fetching games from IGDB", failure) => continue,
+            _ => break (games, warnings),
         }
+    };
+    for warning in warnings.iter() {
+        warn!("{}", warning);
     }
+    info!("Indexed {} games.", games.len());
 
-    api::start(&config, &mut last_request, games).unwrap();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let image_sizes = api::ImageSizes {
+        original: (config.original_max_width, config.original_max_height),
+        thumbnail: config.thumbnail_max(),
+        medium: (Some(config.medium_max_width), None),
+    };
+    let image_source = api::ImageSource {
+        agent,
+        host: config.image_host.clone(),
+        retries: config.request_retries,
+    };
+    let (failure_sender, failure_receiver) = crossbeam_channel::unbounded();
+    api::remove_partial_images();
+    // Games that failed to fetch would look like they don't use their images anymore.
+    if config.remove_unused_images && !game::is_incomplete(&warnings) {
+        api::remove_unused_images(&games);
+    }
+    let cache_mb = api::cache_bytes() / 1_000_000;
+    match config.cache_max_bytes {
+        Some(max_bytes) => info!(
+            "The image cache is using {} MB of {} MB.",
+            cache_mb,
+            max_bytes / 1_000_000
+        ),
+        None => info!("The image cache is using {} MB.", cache_mb),
+    }
+    let cache_usage = Arc::new(api::CacheUsage::load());
+    let prefetch_usage = Arc::clone(&cache_usage);
+    let prefetch_pool = std::thread::spawn(move || {
+        api::image_prefetch_pool(
+            prefetch_threads,
+            image_sizes,
+            image_source,
+            receiver,
+            failure_sender,
+            prefetch_usage,
+        );
+    });
+
+    let warnings = config_warnings
+        .iter()
+        .map(api::IndexWarning::from)
+        .chain(warnings.iter().map(api::IndexWarning::from))
+        .collect();
+    api::start(
+        &config,
+        metadata,
+        games,
+        warnings,
+        api::Prefetcher {
+            queue: sender,
+            pool: prefetch_pool,
+            failures: failure_receiver,
+        },
+        cache_usage,
+    )?;
     Ok(())
 }
+
+// Spaces out retries of the requests made on startup, doubling the wait each time, until the
+// grace period is up.
+struct StartupRetry {
+    deadline: Instant,
+    delay: Duration,
+}
+
+impl StartupRetry {
+    fn new(grace_period: Duration) -> Self {
+        StartupRetry {
+            deadline: Instant::now() + grace_period,
+            delay: Duration::from_secs(1),
+        }
+    }
+
+    // Waits before the next attempt, or returns false when there's no time left for one.
+    fn wait(&mut self, what: &str, err: &dyn Display) -> bool {
+        if Instant::now() + self.delay > self.deadline {
+            return false;
+        }
+        warn!(
+            "{} failed ({}), retrying in {} seconds.",
+            what,
+            err,
+            self.delay.as_secs()
+        );
+        std::thread::sleep(self.delay);
+        self.delay = Duration::min(self.delay * 2, Duration::from_secs(30));
+        true
+    }
+}
+
+// `grifter --config-check` checks the config without starting the server, and `--json` prints
+// the result for scripts and editors instead of people. Returns the exit status, which is 0 only
+// when the config is ok.
+fn check_config(as_json: bool) -> i32 {
+    let (errors, warnings) = match fs::read_to_string(config::FILENAME) {
+        Ok(text) => match Config::from_str(&text) {
+            Ok((_, warnings)) => (Vec::new(), warnings),
+            Err(err) => (vec![serde_json::to_value(&err).unwrap()], Vec::new()),
+        },
+        Err(err) => {
+            let error = serde_json::json!({
+                "code": "unreadable_config",
+                "message": format!("couldn't read {}: {}", config::FILENAME, err),
+            });
+            (vec![error], Vec::new())
+        }
+    };
+    let is_ok = errors.is_empty();
+
+    if as_json {
+        let result = serde_json::json!({
+            "ok": is_ok,
+            "errors": errors,
+            "warnings": warnings,
+        });
+        println!("{}", result);
+    } else {
+        for error in errors.iter() {
+            println!("Error: {}", error["message"].as_str().unwrap_or_default());
+        }
+        for warning in warnings.iter() {
+            println!("Warning: {}", warning);
+        }
+        if is_ok {
+            println!("{} is ok.", config::FILENAME);
+        }
+    }
+
+    if is_ok {
+        0
+    } else {
+        1
+    }
+}
+
+// `grifter --hash-password` turns a password into a hash for the [auth] section of the config.
+// It's read from stdin instead of the arguments so it doesn't end up in the shell's history.
+fn hash_password() -> i32 {
+    println!("Type the password and press enter:");
+    let mut password = String::new();
+    if let Err(err) = std::io::stdin().read_line(&mut password) {
+        println!("Couldn't read the password: {}", err);
+        return 1;
+    }
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.is_empty() {
+        println!("The password can't be empty.");
+        return 1;
+    }
+    match bcrypt::hash(password, bcrypt::DEFAULT_COST) {
+        Ok(hash) => {
+            println!("password_hash = '{}'", hash);
+            0
+        }
+        Err(err) => {
+            println!("Couldn't hash the password: {}", err);
+            1
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::game::normalize_slug;
+use crate::igdb::{self, FailedBatch, Game, GameRef, Genre, Keyword, Theme};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Where game metadata comes from. IGDB is the default, but anything that can produce
+// IGDB-shaped games can be plugged in here without touching the rest of the indexer.
+pub trait MetadataProvider {
+    fn fetch_games(&mut self, games: &[GameRef]) -> (Vec<Game>, Vec<FailedBatch>);
+    fn fetch_keywords(&mut self, ids: &[u64]) -> Result<Vec<Keyword>, igdb::Error>;
+    fn fetch_genres(&mut self) -> Result<Vec<Genre>, igdb::Error>;
+    fn fetch_themes(&mut self) -> Result<Vec<Theme>, igdb::Error>;
+}
+
+impl MetadataProvider for igdb::Client {
+    fn fetch_games(&mut self, games: &[GameRef]) -> (Vec<Game>, Vec<FailedBatch>) {
+        self.get_games(games)
+    }
+
+    fn fetch_keywords(&mut self, ids: &[u64]) -> Result<Vec<Keyword>, igdb::Error> {
+        self.get_keywords(ids)
+    }
+
+    fn fetch_genres(&mut self) -> Result<Vec<Genre>, igdb::Error> {
+        self.get_genres()
+    }
+
+    fn fetch_themes(&mut self) -> Result<Vec<Theme>, igdb::Error> {
+        self.get_themes()
+    }
+}
+
+// Reads metadata from a local JSON file instead of IGDB. The file has the same shape as
+// IGDB's responses, so it can be written by hand or saved from IGDB queries:
+//
+//     { "games": [...], "genres": [...], "themes": [...], "keywords": [...] }
+//
+// Only "games" is required.
+#[derive(Deserialize)]
+pub struct FileProvider {
+    games: Vec<Game>,
+    #[serde(default)]
+    genres: Vec<Genre>,
+    #[serde(default)]
+    themes: Vec<Theme>,
+    #[serde(default)]
+    keywords: Vec<Keyword>,
+}
+
+impl FileProvider {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl MetadataProvider for FileProvider {
+    fn fetch_games(&mut self, games: &[GameRef]) -> (Vec<Game>, Vec<FailedBatch>) {
+        let games = self
+            .games
+            .iter()
+            .filter(|game| games.iter().any(|r| r.matches(game.id, &game.slug)))
+            .cloned()
+            .collect();
+        (games, Vec::new())
+    }
+
+    fn fetch_keywords(&mut self, ids: &[u64]) -> Result<Vec<Keyword>, igdb::Error> {
+        Ok(self
+            .keywords
+            .iter()
+            .filter(|keyword| ids.contains(&keyword.id))
+            .cloned()
+            .collect())
+    }
+
+    fn fetch_genres(&mut self) -> Result<Vec<Genre>, igdb::Error> {
+        Ok(self.genres.clone())
+    }
+
+    fn fetch_themes(&mut self) -> Result<Vec<Theme>, igdb::Error> {
+        Ok(self.themes.clone())
+    }
+}
+
+// Keeps what another provider fetched in cache/metadata.json, so restarting doesn't mean asking
+// IGDB for every game again. Anything cached longer ago than `max_age` is fetched again, and
+// if that fails the old copy is used anyway, since it's better than nothing.
+pub struct CachedProvider<P: MetadataProvider> {
+    provider: P,
+    max_age: u64,
+    cache: MetadataCache,
+}
+
+const METADATA_CACHE: &str = "./cache/metadata.json";
+
+#[derive(Default, Serialize, Deserialize)]
+struct MetadataCache {
+    #[serde(default)]
+    games: HashMap<String, Cached<Game>>, // By normalized slug
+    #[serde(default)]
+    keywords: HashMap<u64, Cached<Keyword>>,
+    genres: Option<Cached<Vec<Genre>>>,
+    themes: Option<Cached<Vec<Theme>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cached<T> {
+    fetched_at: u64, // Unix timestamp
+    value: T,
+}
+
+impl<T> Cached<T> {
+    fn new(value: T) -> Self {
+        Cached {
+            fetched_at: now(),
+            value,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+impl<P: MetadataProvider> CachedProvider<P> {
+    // With `refresh` the old cache is ignored, but whatever's fetched is still cached.
+    pub fn open(provider: P, max_age: Duration, refresh: bool) -> Self {
+        let cache = if refresh {
+            MetadataCache::default()
+        } else {
+            fs::read(METADATA_CACHE)
+                .ok()
+                .and_then(|cache| serde_json::from_slice(&cache).ok())
+                .unwrap_or_default()
+        };
+        CachedProvider {
+            provider,
+            max_age: max_age.as_secs(),
+            cache,
+        }
+    }
+
+    // Games are cached by slug, so the ones asked for by id have to be looked for.
+    fn cached_game(&self, game: &GameRef) -> Option<&Cached<Game>> {
+        match game {
+            GameRef::Slug(slug) => self.cache.games.get(&normalize_slug(slug)),
+            GameRef::Id(id) => self
+                .cache
+                .games
+                .values()
+                .find(|cached| cached.value.id == *id),
+        }
+    }
+
+    fn is_fresh<T>(&self, cached: &Cached<T>) -> bool {
+        now().saturating_sub(cached.fetched_at) < self.max_age
+    }
+
+    fn save(&self) {
+        let saved = serde_json::to_vec(&self.cache)
+            .map_err(io::Error::from)
+            .and_then(|cache| {
+                let part = format!("{}.part", METADATA_CACHE);
+                fs::create_dir_all(Path::new(METADATA_CACHE).parent().unwrap())?;
+                fs::write(&part, cache)?;
+                fs::rename(&part, METADATA_CACHE)
+            });
+        if let Err(err) = saved {
+            warn!("Failed to cache metadata: {}", err);
+        }
+    }
+}
+
+impl<P: MetadataProvider> MetadataProvider for CachedProvider<P> {
+    fn fetch_games(&mut self, games: &[GameRef]) -> (Vec<Game>, Vec<FailedBatch>) {
+        let stale: Vec<GameRef> = games
+            .iter()
+            .filter(|game| match self.cached_game(game) {
+                Some(cached) => !self.is_fresh(cached),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        let mut failed_batches = Vec::new();
+        if !stale.is_empty() {
+            let (fetched, failures) = self.provider.fetch_games(&stale);
+            for game in fetched {
+                let slug = normalize_slug(&game.slug);
+                self.cache.games.insert(slug, Cached::new(game));
+            }
+            for mut batch in failures {
+                batch.games.retain(|game| self.cached_game(game).is_none());
+                if !batch.games.is_empty() {
+                    failed_batches.push(batch);
+                }
+            }
+            self.save();
+        }
+
+        let games = games
+            .iter()
+            .filter_map(|game| self.cached_game(game))
+            .map(|cached| cached.value.clone())
+            .collect();
+        (games, failed_batches)
+    }
+
+    fn fetch_keywords(&mut self, ids: &[u64]) -> Result<Vec<Keyword>, igdb::Error> {
+        let stale: Vec<u64> = ids
+            .iter()
+            .filter(|id| match self.cache.keywords.get(*id) {
+                Some(cached) => !self.is_fresh(cached),
+                None => true,
+            })
+            .copied()
+            .collect();
+        if !stale.is_empty() {
+            match self.provider.fetch_keywords(&stale) {
+                Ok(keywords) => {
+                    for keyword in keywords {
+                        self.cache.keywords.insert(keyword.id, Cached::new(keyword));
+                    }
+                    self.save();
+                }
+                Err(err) if stale.iter().any(|id| !self.cache.keywords.contains_key(id)) => {
+                    return Err(err)
+                }
+                Err(err) => warn!("Using cached keywords, fetching them failed: {}", err),
+            }
+        }
+        Ok(ids
+            .iter()
+            .filter_map(|id| self.cache.keywords.get(id))
+            .map(|cached| cached.value.clone())
+            .collect())
+    }
+
+    fn fetch_genres(&mut self) -> Result<Vec<Genre>, igdb::Error> {
+        match &self.cache.genres {
+            Some(cached) if self.is_fresh(cached) => return Ok(cached.value.clone()),
+            _ => {}
+        }
+        match self.provider.fetch_genres() {
+            Ok(genres) => {
+                self.cache.genres = Some(Cached::new(genres.clone()));
+                self.save();
+                Ok(genres)
+            }
+            Err(err) => match &self.cache.genres {
+                Some(cached) => {
+                    warn!("Using cached genres, fetching them failed: {}", err);
+                    Ok(cached.value.clone())
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    fn fetch_themes(&mut self) -> Result<Vec<Theme>, igdb::Error> {
+        match &self.cache.themes {
+            Some(cached) if self.is_fresh(cached) => return Ok(cached.value.clone()),
+            _ => {}
+        }
+        match self.provider.fetch_themes() {
+            Ok(themes) => {
+                self.cache.themes = Some(Cached::new(themes.clone()));
+                self.save();
+                Ok(themes)
+            }
+            Err(err) => match &self.cache.themes {
+                Some(cached) => {
+                    warn!("Using cached themes, fetching them failed: {}", err);
+                    Ok(cached.value.clone())
+                }
+                None => Err(err),
+            },
+        }
+    }
+}
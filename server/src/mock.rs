@@ -0,0 +1,112 @@
+// A tiny HTTP server for tests. It answers each request with the next of its canned responses,
+// and keeps what was sent so the test can check it.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub struct Recorded {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Names and values.
+pub type Headers<'a> = &'a [(&'a str, &'a str)];
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl MockServer {
+    // Every response is sent as JSON, one per connection.
+    pub fn start(responses: Vec<(u16, &str)>) -> MockServer {
+        let responses = responses
+            .into_iter()
+            .map(|(status, body)| (status, &[][..], body))
+            .collect();
+        MockServer::start_with_headers(responses)
+    }
+
+    // The same, with more headers on each response.
+    pub fn start_with_headers(responses: Vec<(u16, Headers, &str)>) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let responses: Vec<(u16, String, String)> = responses
+            .into_iter()
+            .map(|(status, headers, body)| {
+                let headers = headers
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}\r\n", key, value))
+                    .collect();
+                (status, headers, body.to_string())
+            })
+            .collect();
+        thread::spawn(move || {
+            for (status, extra_headers, body) in responses {
+                let (mut stream, _) = match listener.accept() {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap_or("").to_string();
+                let path = parts.next().unwrap_or("").to_string();
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((key, value)) = line.split_once(':') {
+                        headers.push((key.trim().to_string(), value.trim().to_string()));
+                    }
+                }
+                let len = headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.parse().ok())
+                    .unwrap_or(0);
+                let mut request_body = vec![0; len];
+                reader.read_exact(&mut request_body).unwrap();
+                recorded.lock().unwrap().push(Recorded {
+                    method,
+                    path,
+                    headers,
+                    body: String::from_utf8_lossy(&request_body).into_owned(),
+                });
+
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n{}\r\n{}",
+                    status,
+                    body.len(),
+                    extra_headers,
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        MockServer { url, requests }
+    }
+
+    pub fn requests(&self) -> std::sync::MutexGuard<'_, Vec<Recorded>> {
+        self.requests.lock().unwrap()
+    }
+}
@@ -0,0 +1,487 @@
+// Connections the server library can't accept itself, like ones on a Unix socket or ones that
+// need an idle timeout (it never hands over its sockets to set one on), are accepted here and
+// relayed to a server that only listens on a loopback port. That server only ever sees the
+// relay's connection, so who's really on the other end is kept track of in `Peers`.
+use crate::shutdown;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// How often a connection that's waiting on the other end checks whether it's been idle too long.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// How often the listeners check for a shutdown while no one's connecting.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+// The address of whoever's on the other end of each relayed connection, by the address the relay
+// connected to the server from, and how many connections are being relayed.
+pub struct Peers {
+    addrs: Mutex<HashMap<SocketAddr, IpAddr>>,
+    connections: AtomicUsize,
+    max_connections: usize,
+}
+
+impl Peers {
+    // Each relayed connection takes two threads, so only `max_connections` are relayed at once,
+    // and any more are closed right away. Otherwise a client that sends a byte every so often to
+    // stay under the idle timeout could open as many threads as it likes.
+    pub fn new(max_connections: usize) -> Peers {
+        Peers {
+            addrs: Mutex::default(),
+            connections: AtomicUsize::new(0),
+            max_connections,
+        }
+    }
+
+    // None for connections that didn't come through the relay, like something else on this
+    // machine connecting to the loopback port directly.
+    pub fn get(&self, relay_addr: &SocketAddr) -> Option<IpAddr> {
+        lock(&self.addrs).get(relay_addr).copied()
+    }
+}
+
+// A relayed connection's place under `Peers::max_connections`, given back when it's dropped.
+struct Slot(Arc<Peers>);
+
+impl Slot {
+    fn take(peers: &Arc<Peers>) -> Option<Slot> {
+        peers
+            .connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+                if connections < peers.max_connections {
+                    Some(connections + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+        Some(Slot(Arc::clone(peers)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        std::os::unix::net::UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// Passes bytes both ways between the client and the server at `upstream` until both sides are
+// done, or nothing's been sent either way for `idle_timeout`. Uploads are copied on a thread of
+// their own and downloads on this one.
+fn relay<S: Stream>(
+    client: S,
+    peer: IpAddr,
+    upstream: SocketAddr,
+    idle_timeout: Option<Duration>,
+    peers: &Peers,
+) {
+    let server = match TcpStream::connect(upstream) {
+        Ok(server) => server,
+        Err(err) => {
+            warn!("Couldn't relay a connection to {}: {}", upstream, err);
+            return;
+        }
+    };
+    let relay_addr = match server.local_addr() {
+        Ok(relay_addr) => relay_addr,
+        Err(_) => return,
+    };
+    if let Some(idle_timeout) = idle_timeout {
+        // Reads wake up every so often to check on the timeout, and a write to a client that's
+        // stopped reading gives up once it's been stuck for the whole timeout.
+        let poll = Duration::min(idle_timeout, IDLE_CHECK_INTERVAL);
+        let set = client
+            .set_read_timeout(Some(poll))
+            .and(client.set_write_timeout(Some(idle_timeout)))
+            .and(server.set_read_timeout(Some(poll)))
+            .and(server.set_write_timeout(Some(idle_timeout)));
+        if set.is_err() {
+            return;
+        }
+    }
+    let (client_reader, server_writer) = match (client.try_clone(), server.try_clone()) {
+        (Ok(client_reader), Ok(server_writer)) => (client_reader, server_writer),
+        _ => return,
+    };
+
+    lock(&peers.addrs).insert(relay_addr, peer);
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let upload_activity = Arc::clone(&last_activity);
+    let upload =
+        thread::spawn(move || pipe(client_reader, server_writer, idle_timeout, &upload_activity));
+    pipe(server, client, idle_timeout, &last_activity);
+    let _ = upload.join();
+    lock(&peers.addrs).remove(&relay_addr);
+}
+
+fn pipe<R: Stream, W: Stream>(
+    mut from: R,
+    mut to: W,
+    idle_timeout: Option<Duration>,
+    last_activity: &Mutex<Instant>,
+) {
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let len = match from.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // The other direction might still be busy, like with a big download, so the
+            // connection only counts as idle when neither has sent anything in a while.
+            Err(err)
+                if is_timeout(&err)
+                    && idle_timeout.map_or(false, |idle_timeout| {
+                        lock(last_activity).elapsed() < idle_timeout
+                    }) =>
+            {
+                continue
+            }
+            Err(_) => {
+                // Nothing else is getting through either, so the other direction can stop too.
+                let _ = from.shutdown(Shutdown::Both);
+                let _ = to.shutdown(Shutdown::Both);
+                return;
+            }
+        };
+        *lock(last_activity) = Instant::now();
+        if to.write_all(&buffer[..len]).is_err() {
+            let _ = from.shutdown(Shutdown::Both);
+            let _ = to.shutdown(Shutdown::Both);
+            return;
+        }
+        *lock(last_activity) = Instant::now();
+    }
+    // Passes the end of the stream along, the same as if this side had closed it.
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+// Which of these a timed out read returns depends on the platform.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// Accepts connections until a shutdown is requested. The listener has to be nonblocking, so it
+// stops right away instead of waiting on one more connection first.
+fn accept_until_shutdown<S: Stream>(
+    accept: impl Fn() -> io::Result<(S, IpAddr)>,
+    upstream: SocketAddr,
+    idle_timeout: Option<Duration>,
+    peers: Arc<Peers>,
+) {
+    while !shutdown::is_requested() {
+        let (client, peer) = match accept() {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    warn!("Failed to accept a connection: {}", err);
+                }
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+        };
+        let slot = match Slot::take(&peers) {
+            Some(slot) => slot,
+            None => {
+                warn!(
+                    "Turned away a connection from {}, there are already {} open",
+                    peer, peers.max_connections
+                );
+                continue;
+            }
+        };
+        // Some platforms pass nonblocking on from the listener.
+        if let Err(err) = client.set_nonblocking(false) {
+            warn!("Failed to accept a connection: {}", err);
+            continue;
+        }
+        thread::spawn(move || {
+            relay(client, peer, upstream, idle_timeout, &slot.0);
+            drop(slot);
+        });
+    }
+}
+
+// Relays every connection to `listener` to the server at `upstream`.
+pub fn serve_tcp(
+    listener: TcpListener,
+    upstream: SocketAddr,
+    idle_timeout: Option<Duration>,
+    peers: Arc<Peers>,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let accept = move || {
+        let (client, peer) = listener.accept()?;
+        Ok((client, peer.ip()))
+    };
+    thread::spawn(move || accept_until_shutdown(accept, upstream, idle_timeout, peers));
+    Ok(())
+}
+
+// Listens on a Unix socket at `path`, readable and writable by whoever `mode` (like 0o660)
+// allows, and relays every connection to the server at `upstream`. Clients on a Unix socket don't
+// have an IP address, so they show up as localhost.
+#[cfg(unix)]
+pub fn serve_unix(
+    path: &Path,
+    mode: u32,
+    upstream: SocketAddr,
+    idle_timeout: Option<Duration>,
+    peers: Arc<Peers>,
+) -> io::Result<()> {
+    use std::net::Ipv4Addr;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    listener.set_nonblocking(true)?;
+    let accept = move || {
+        let (client, _) = listener.accept()?;
+        Ok((client, IpAddr::V4(Ipv4Addr::LOCALHOST)))
+    };
+    thread::spawn(move || accept_until_shutdown(accept, upstream, idle_timeout, peers));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve_unix(
+    _: &Path,
+    _: u32,
+    _: SocketAddr,
+    _: Option<Duration>,
+    _: Arc<Peers>,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "unix_socket only works on Linux, macOS, and other Unix-like systems",
+    ))
+}
+
+// A socket left behind by a server that didn't get to shut down cleanly would keep a new one
+// from binding. It's only removed when nothing answers on it, so starting a second server can't
+// pull the socket out from under one that's still running.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} is already there and isn't a socket", path),
+        ));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another server is already listening on {:?}", path),
+        )),
+        Err(_) => std::fs::remove_file(path),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    #[test]
+    fn stale_sockets_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("grifter.sock");
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn live_sockets_and_other_files_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path().join("live.sock");
+        let _listener = UnixListener::bind(&live).unwrap();
+        let err = remove_stale_socket(&live).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(live.exists());
+
+        let file = dir.path().join("grifter.toml");
+        std::fs::write(&file, "").unwrap();
+        let err = remove_stale_socket(&file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn connections_are_relayed_with_their_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("grifter.sock");
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let upstream = server.local_addr().unwrap();
+        let peers = Arc::new(Peers::new(16));
+        serve_unix(&path, 0o600, upstream, None, Arc::clone(&peers)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"ping").unwrap();
+        let (mut relayed, relay_addr) = server.accept().unwrap();
+        let mut received = [0; 4];
+        relayed.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"ping");
+        assert_eq!(
+            peers.get(&relay_addr),
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+
+        relayed.write_all(b"pong").unwrap();
+        drop(relayed);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"pong");
+    }
+
+    #[test]
+    fn idle_connections_are_closed() {
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let upstream = server.local_addr().unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peers = Arc::new(Peers::new(16));
+        let idle_timeout = Duration::from_secs(1);
+        serve_tcp(listener, upstream, Some(idle_timeout), Arc::clone(&peers)).unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let (mut relayed, relay_addr) = server.accept().unwrap();
+
+        // Trickling bytes in keeps the connection open past the timeout...
+        for _ in 0..4 {
+            thread::sleep(idle_timeout / 4);
+            client.write_all(b".").unwrap();
+        }
+        let last_write = Instant::now();
+        let mut received = [0; 4];
+        relayed.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"....");
+        assert_eq!(
+            peers.get(&relay_addr),
+            Some(client.local_addr().unwrap().ip())
+        );
+
+        // ...and going quiet closes it on both ends.
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        assert!(last_write.elapsed() >= idle_timeout);
+        relayed.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn connections_past_the_limit_are_turned_away() {
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let upstream = server.local_addr().unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peers = Arc::new(Peers::new(1));
+        serve_tcp(listener, upstream, None, Arc::clone(&peers)).unwrap();
+
+        let first = TcpStream::connect(addr).unwrap();
+        let (relayed, _) = server.accept().unwrap();
+        let mut second = TcpStream::connect(addr).unwrap();
+        let mut rest = Vec::new();
+        second.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        server.set_nonblocking(true).unwrap();
+        let err = server.accept().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        // Once the first one's done, there's room for another.
+        drop(first);
+        drop(relayed);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while peers.connections.load(Ordering::SeqCst) > 0 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.set_nonblocking(false).unwrap();
+        let mut third = TcpStream::connect(addr).unwrap();
+        third.write_all(b"ping").unwrap();
+        let (mut relayed, _) = server.accept().unwrap();
+        let mut received = [0; 4];
+        relayed.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"ping");
+    }
+}
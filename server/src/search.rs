@@ -0,0 +1,301 @@
+use crate::game::{self, Game};
+use std::cmp::Ordering;
+
+// Scores for the different kinds of matches. Anything that contains the query outright beats
+// a fuzzy match, which always scores below 1.
+const EXACT: f64 = 4.0;
+const PREFIX: f64 = 3.0;
+const WORD_PREFIX: f64 = 2.0;
+const SUBSTRING: f64 = 1.0;
+
+// Finds the games with a name that's similar enough to the query, best matches first. The
+// threshold is how similar (from 0 to 1) a fuzzy match has to be to count.
+pub fn search<'a>(games: &'a [Game], query: &str, threshold: f64) -> Vec<(&'a Game, f64)> {
+    let query = game::normalize(query);
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut results: Vec<(&Game, f64)> = games
+        .iter()
+        .filter(|game| !game.hidden)
+        .filter_map(|game| {
+            let score = game
+                .search_names
+                .iter()
+                .map(|name| score(name, &query))
+                .fold(0.0, f64::max);
+            if score >= threshold {
+                Some((game, score))
+            } else {
+                None
+            }
+        })
+        .collect();
+    results.sort_by(|(a, a_score), (b, b_score)| {
+        b_score
+            .partial_cmp(a_score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| game::natural_cmp(&a.name, &b.name))
+    });
+    results
+}
+
+// What /api/search asks for. Games have to match every filter that's set, and without any text
+// every game that does is listed by name instead of by how well it matches. `store::Store`
+// answers the same queries with SQL.
+pub struct Query {
+    pub text: String,
+    pub genre: Option<u64>,
+    pub theme: Option<u64>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Query {
+    // Nothing to search for, so nothing is found rather than every game.
+    pub fn is_empty(&self) -> bool {
+        game::normalize(&self.text).is_empty() && self.genre.is_none() && self.theme.is_none()
+    }
+}
+
+pub fn query<'a>(games: &'a [Game], query: &Query, threshold: f64) -> Vec<&'a Game> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let found: Vec<&Game> = if game::normalize(&query.text).is_empty() {
+        // The catalog is already sorted by name.
+        games.iter().filter(|game| !game.hidden).collect()
+    } else {
+        search(games, &query.text, threshold)
+            .into_iter()
+            .map(|(game, _)| game)
+            .collect()
+    };
+    found
+        .into_iter()
+        .filter(|game| {
+            query
+                .genre
+                .map_or(true, |genre| game.genres.contains(&genre))
+        })
+        .filter(|game| {
+            query
+                .theme
+                .map_or(true, |theme| game.themes.contains(&theme))
+        })
+        .skip(query.offset)
+        .take(query.limit)
+        .collect()
+}
+
+// Both the name and query should already be normalized.
+pub fn score(name: &str, query: &str) -> f64 {
+    if name == query {
+        return EXACT;
+    }
+    if name.starts_with(query) {
+        return PREFIX;
+    }
+    if name.split(' ').any(|word| word.starts_with(query)) {
+        return WORD_PREFIX;
+    }
+    if name.contains(query) {
+        return SUBSTRING;
+    }
+
+    // Compare the query against the whole name and against every run of words starting at a
+    // word boundary, cut to the query's length, so "legnd of zelda" still finds "the legend
+    // of zelda".
+    let query: Vec<char> = query.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let word_starts = (0..name.len()).filter(|&i| i == 0 || name[i - 1] == ' ');
+    let candidates = word_starts
+        .map(|start| &name[start..usize::min(start + query.len(), name.len())])
+        .chain(std::iter::once(&name[..]));
+    candidates
+        .map(|candidate| similarity(candidate, &query))
+        .fold(0.0, f64::max)
+}
+
+// 1 for identical strings down to 0 for nothing in common.
+fn similarity(a: &[char], b: &[char]) -> f64 {
+    let longest = usize::max(a.len(), b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(a, b) as f64 / longest as f64
+}
+
+// The number of insertions, deletions, substitutions, and swaps of neighboring characters it
+// takes to turn one string into the other. Swaps are counted since they're such a common typo.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = usize::min(
+                usize::min(rows[i - 1][j] + 1, rows[i][j - 1] + 1),
+                rows[i - 1][j - 1] + cost,
+            );
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = usize::min(distance, rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::config::tests::config_for;
+    use crate::config::Config;
+    use crate::metadata::FileProvider;
+    use serde_json::json;
+
+    // Games from IGDB by slug, name, genres, and themes, indexed like they would be for real.
+    pub fn library(games: &[(&str, &str, &[u64], &[u64])]) -> Vec<Game> {
+        let root = tempfile::tempdir().unwrap();
+        let mut config = config_for(root.path(), "https = false");
+        let mut igdb_games = Vec::new();
+        for (i, &(slug, name, genres, themes)) in games.iter().enumerate() {
+            config += &format!("[[games]]\npath = '{0}.zip'\nslug = '{0}'\n", slug);
+            igdb_games.push(json!({
+                "id": i + 1,
+                "slug": slug,
+                "name": name,
+                "updated_at": 0,
+                "genres": genres,
+                "themes": themes,
+            }));
+        }
+        let (config, _) = Config::from_str(&config).unwrap();
+        let mut metadata: FileProvider =
+            serde_json::from_value(json!({ "games": igdb_games })).unwrap();
+        game::games_from_config(&config, &mut metadata).unwrap().0
+    }
+
+    pub fn zelda_library() -> Vec<Game> {
+        library(&[
+            ("zelda", "The Legend of Zelda", &[31], &[1]),
+            (
+                "zelda-ii",
+                "Zelda II: The Adventure of Link",
+                &[12, 31],
+                &[1],
+            ),
+            ("link-s-awakening", "Link's Awakening", &[31], &[1, 17]),
+            ("portal", "Portal", &[5, 9], &[20]),
+            ("metroid", "Metroid", &[8, 31], &[18]),
+        ])
+    }
+
+    fn slugs(games: Vec<&Game>) -> Vec<&str> {
+        games.into_iter().map(|game| game.slug.as_str()).collect()
+    }
+
+    pub fn search_for(text: &str, genre: Option<u64>, theme: Option<u64>) -> Query {
+        Query {
+            text: text.to_string(),
+            genre,
+            theme,
+            offset: 0,
+            limit: 20,
+        }
+    }
+
+    #[test]
+    fn query_filters_and_pages() {
+        let games = zelda_library();
+        let found = |query: &Query| slugs(super::query(&games, query, 0.7));
+
+        assert!(found(&search_for("", None, None)).is_empty());
+        assert_eq!(
+            found(&search_for("zelda", None, None)),
+            ["zelda-ii", "zelda"]
+        );
+        assert_eq!(found(&search_for("zelda", Some(12), None)), ["zelda-ii"]);
+        // Without any text, everything that matches the filters is listed by name.
+        assert_eq!(
+            found(&search_for("", Some(31), None)),
+            ["link-s-awakening", "metroid", "zelda", "zelda-ii"]
+        );
+        assert_eq!(
+            found(&search_for("", Some(31), Some(1))),
+            ["link-s-awakening", "zelda", "zelda-ii"]
+        );
+
+        let mut page = search_for("", Some(31), None);
+        page.offset = 1;
+        page.limit = 2;
+        assert_eq!(found(&page), ["metroid", "zelda"]);
+        page.offset = 4;
+        assert!(found(&page).is_empty());
+    }
+
+    fn distance(a: &str, b: &str) -> usize {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        edit_distance(&a, &b)
+    }
+
+    #[test]
+    fn edit_distance_counts_each_kind_of_edit() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("zelda", "zelda"), 0);
+        assert_eq!(distance("", "zelda"), 5);
+        assert_eq!(distance("zelda", ""), 5);
+        assert_eq!(distance("zelda", "zeld"), 1);
+        assert_eq!(distance("zeld", "zelda"), 1);
+        assert_eq!(distance("zelda", "zelka"), 1);
+        assert_eq!(distance("zelda", "zedla"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn score_ranks_kinds_of_matches() {
+        assert_eq!(score("portal 2", "portal 2"), EXACT);
+        assert_eq!(score("portal 2", "port"), PREFIX);
+        assert_eq!(score("the legend of zelda", "zel"), WORD_PREFIX);
+        assert_eq!(score("the legend of zelda", "gend"), SUBSTRING);
+    }
+
+    #[test]
+    fn score_is_fuzzy_below_substring() {
+        let typo = score("the legend of zelda", "legnd of zelda");
+        assert!(typo > 0.8 && typo < SUBSTRING, "{}", typo);
+        let swapped = score("portal", "protal");
+        assert!(swapped > 0.8 && swapped < SUBSTRING, "{}", swapped);
+        let unrelated = score("portal", "doom");
+        assert!(unrelated < 0.5, "{}", unrelated);
+    }
+
+    #[test]
+    fn search_normalizes_the_query() {
+        let games = zelda_library();
+        let best = |query: &str| {
+            search(&games, query, 0.7)
+                .into_iter()
+                .next()
+                .map(|(game, _)| game.slug.as_str())
+        };
+        // The Arabic letter is dropped and the space trimmed, leaving a near miss.
+        assert_eq!(best(" زelda"), Some("zelda"));
+        assert_eq!(best("  THE LÉGEND of ZÉLDA!  "), Some("zelda"));
+        assert_eq!(best("Légnd Of Zëlda"), Some("zelda"));
+    }
+
+    #[test]
+    fn similarity_of_empty_strings() {
+        assert_eq!(similarity(&[], &[]), 1.0);
+        assert_eq!(similarity(&['a'], &[]), 0.0);
+    }
+}
@@ -0,0 +1,25 @@
+use once_cell::sync::Lazy;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Set by the first Ctrl-C (or SIGTERM, like when systemd stops the service) after `listen` is
+// called, which asks for a clean shutdown that waits on downloads and images in progress. A
+// second one ends the process right away.
+static REQUESTED: Lazy<Arc<AtomicBool>> = Lazy::new(Default::default);
+
+// Until this is called, Ctrl-C ends the process right away like usual.
+pub fn listen() -> io::Result<()> {
+    for &signal in [SIGINT, SIGTERM].iter() {
+        // Registered first so it only sees the flag as it was before this same signal.
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(&REQUESTED))?;
+        flag::register(signal, Arc::clone(&REQUESTED))?;
+    }
+    Ok(())
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,367 @@
+// An optional copy of the catalog in SQLite, for libraries with thousands of games where going
+// through every one of them on each search, and serializing all of them again on every change,
+// adds up. Searches, filters, and pages are answered with SQL then, the same as `search::query`
+// would answer them, and the catalog and single games are served from the JSON saved here. The
+// games are still kept in memory for downloads and images. It needs grifter built with
+// `--features sqlite`, and is turned on with `catalog_database` in the config.
+use crate::game::Game;
+use crate::search::Query;
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::Store;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+    use crate::game;
+    use crate::search;
+    use rusqlite::functions::FunctionFlags;
+    use rusqlite::{params, Connection, OptionalExtension, ToSql};
+    use std::convert::TryInto;
+    use std::sync::{Mutex, PoisonError};
+
+    // Rebuilt from scratch on every index, so there's nothing to migrate when it changes.
+    // `position` is where the game is in the catalog, which is sorted by name. Every run of three
+    // characters in each search name is indexed in `search_trigrams`, so a search only scores
+    // the names that have something in common with it.
+    const SCHEMA: &str = "
+        DROP TABLE IF EXISTS game_themes;
+        DROP TABLE IF EXISTS game_genres;
+        DROP TABLE IF EXISTS search_trigrams;
+        DROP TABLE IF EXISTS search_names;
+        DROP TABLE IF EXISTS games;
+        CREATE TABLE games (
+            position INTEGER PRIMARY KEY,
+            slug TEXT NOT NULL,
+            hidden INTEGER NOT NULL,
+            json TEXT NOT NULL
+        );
+        CREATE TABLE search_names (
+            id INTEGER PRIMARY KEY,
+            game INTEGER NOT NULL,
+            name TEXT NOT NULL
+        );
+        CREATE TABLE search_trigrams (trigram TEXT NOT NULL, name INTEGER NOT NULL);
+        CREATE TABLE game_genres (genre INTEGER NOT NULL, game INTEGER NOT NULL);
+        CREATE TABLE game_themes (theme INTEGER NOT NULL, game INTEGER NOT NULL);
+        CREATE INDEX games_by_slug ON games (slug);
+        CREATE INDEX search_names_by_game ON search_names (game);
+        CREATE INDEX search_trigrams_by_trigram ON search_trigrams (trigram, name);
+        CREATE INDEX game_genres_by_genre ON game_genres (genre, game);
+        CREATE INDEX game_themes_by_theme ON game_themes (theme, game);
+    ";
+
+    // ?1 is the genre and ?2 the theme, either of which can be null to not filter by it.
+    const FILTERS: &str = "
+        games.hidden = 0
+        AND (?1 IS NULL OR games.position IN (SELECT game FROM game_genres WHERE genre = ?1))
+        AND (?2 IS NULL OR games.position IN (SELECT game FROM game_themes WHERE theme = ?2))
+    ";
+
+    pub struct Store {
+        connection: Mutex<Connection>,
+    }
+
+    impl Store {
+        pub fn open(path: &Path) -> io::Result<Store> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let connection = Connection::open(path).map_err(to_io)?;
+            // Scored with the same function as searches in memory, so the results are too.
+            connection
+                .create_scalar_function(
+                    "search_score",
+                    2,
+                    FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                    |context| {
+                        let name: Option<String> = context.get(0)?;
+                        let query: String = context.get(1)?;
+                        Ok(name.map_or(0.0, |name| search::score(&name, &query)))
+                    },
+                )
+                .map_err(to_io)?;
+            Ok(Store {
+                connection: Mutex::new(connection),
+            })
+        }
+
+        // Replaces everything in the database with `games`, all at once so searches never see
+        // half of an index.
+        pub fn replace(&self, games: &[Game]) -> io::Result<()> {
+            let mut connection = self.lock();
+            let transaction = connection.transaction().map_err(to_io)?;
+            transaction.execute_batch(SCHEMA).map_err(to_io)?;
+            {
+                let mut insert_game = transaction
+                    .prepare("INSERT INTO games VALUES (?1, ?2, ?3, ?4)")
+                    .map_err(to_io)?;
+                let mut insert_name = transaction
+                    .prepare("INSERT INTO search_names (game, name) VALUES (?1, ?2)")
+                    .map_err(to_io)?;
+                let mut insert_trigram = transaction
+                    .prepare("INSERT INTO search_trigrams VALUES (?1, ?2)")
+                    .map_err(to_io)?;
+                let mut insert_genre = transaction
+                    .prepare("INSERT INTO game_genres VALUES (?1, ?2)")
+                    .map_err(to_io)?;
+                let mut insert_theme = transaction
+                    .prepare("INSERT INTO game_themes VALUES (?1, ?2)")
+                    .map_err(to_io)?;
+                for (position, game) in games.iter().enumerate() {
+                    let position = integer(position);
+                    let json = serde_json::to_string(game)?;
+                    insert_game
+                        .execute(params![position, game.slug, game.hidden, json])
+                        .map_err(to_io)?;
+                    for name in game.search_names.iter() {
+                        insert_name
+                            .execute(params![position, name])
+                            .map_err(to_io)?;
+                        let id = transaction.last_insert_rowid();
+                        for trigram in trigrams(name) {
+                            insert_trigram
+                                .execute(params![trigram, id])
+                                .map_err(to_io)?;
+                        }
+                    }
+                    for &genre in game.genres.iter() {
+                        insert_genre
+                            .execute(params![integer(genre), position])
+                            .map_err(to_io)?;
+                    }
+                    for &theme in game.themes.iter() {
+                        insert_theme
+                            .execute(params![integer(theme), position])
+                            .map_err(to_io)?;
+                    }
+                }
+            }
+            transaction.commit().map_err(to_io)
+        }
+
+        // Saves the games again after they change without being re-indexed, like when their
+        // checksums are filled in. Their names, genres, and themes stay the same then.
+        pub fn update(&self, games: &[Game]) -> io::Result<()> {
+            let mut connection = self.lock();
+            let transaction = connection.transaction().map_err(to_io)?;
+            {
+                let mut update_game = transaction
+                    .prepare("UPDATE games SET json = ?2 WHERE slug = ?1")
+                    .map_err(to_io)?;
+                for game in games {
+                    let json = serde_json::to_string(game)?;
+                    update_game
+                        .execute(params![game.slug, json])
+                        .map_err(to_io)?;
+                }
+            }
+            transaction.commit().map_err(to_io)
+        }
+
+        // The JSON of the game with this slug, hidden or not.
+        pub fn game(&self, slug: &str) -> io::Result<Option<String>> {
+            let connection = self.lock();
+            let mut statement = connection
+                .prepare_cached("SELECT json FROM games WHERE slug = ?1")
+                .map_err(to_io)?;
+            statement
+                .query_row(params![slug], |row| row.get(0))
+                .optional()
+                .map_err(to_io)
+        }
+
+        // The JSON of every game that isn't hidden, in catalog order.
+        pub fn listed_games(&self) -> io::Result<Vec<String>> {
+            let connection = self.lock();
+            let mut statement = connection
+                .prepare_cached("SELECT json FROM games WHERE hidden = 0 ORDER BY position")
+                .map_err(to_io)?;
+            let rows = statement.query_map(params![], |row| row.get(0));
+            rows.and_then(Iterator::collect).map_err(to_io)
+        }
+
+        // The slugs of the games that `query` finds, like `search::query`. Only the names that
+        // share a run of three characters with the query are scored, or that contain a query
+        // shorter than that. A typo can leave nothing in common with a very short query, so those
+        // can find fewer misspelled names than searching in memory.
+        pub fn search(&self, query: &Query, threshold: f64) -> io::Result<Vec<String>> {
+            if query.is_empty() {
+                return Ok(Vec::new());
+            }
+            let text = game::normalize(&query.text);
+            let trigrams = trigrams(&text);
+            let sql = if text.is_empty() {
+                format!(
+                    "SELECT games.slug FROM games WHERE {} \
+                    ORDER BY games.position LIMIT ?3 OFFSET ?4",
+                    FILTERS
+                )
+            } else {
+                let candidates = if trigrams.is_empty() {
+                    "instr(search_names.name, ?5) > 0"
+                } else {
+                    "search_names.id IN (SELECT name FROM search_trigrams \
+                    WHERE trigram IN (SELECT value FROM json_each(?7)))"
+                };
+                format!(
+                    "SELECT games.slug, MAX(search_score(search_names.name, ?5)) AS score \
+                    FROM games JOIN search_names ON search_names.game = games.position \
+                    WHERE {} AND {} GROUP BY games.position HAVING score >= ?6 \
+                    ORDER BY score DESC, games.position LIMIT ?3 OFFSET ?4",
+                    FILTERS, candidates
+                )
+            };
+            let connection = self.lock();
+            let mut statement = connection.prepare_cached(&sql).map_err(to_io)?;
+            let genre = query.genre.map(integer);
+            let theme = query.theme.map(integer);
+            let (limit, offset) = (integer(query.limit), integer(query.offset));
+            let trigram_list = serde_json::to_string(&trigrams)?;
+            let mut params: Vec<&dyn ToSql> = vec![&genre, &theme, &limit, &offset];
+            if !text.is_empty() {
+                params.extend_from_slice(&[&text, &threshold]);
+            }
+            if !trigrams.is_empty() {
+                params.push(&trigram_list);
+            }
+            let rows = statement.query_map(&params, |row| row.get(0));
+            rows.and_then(Iterator::collect).map_err(to_io)
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+            self.connection
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    // Every run of three characters in an already normalized name or query, without repeats.
+    fn trigrams(text: &str) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut trigrams: Vec<String> = chars
+            .windows(3)
+            .map(|window| window.iter().collect())
+            .collect();
+        trigrams.sort();
+        trigrams.dedup();
+        trigrams
+    }
+
+    // SQLite only has signed integers. Nothing here comes close to not fitting.
+    fn integer<T: TryInto<i64>>(number: T) -> i64 {
+        number.try_into().unwrap_or(i64::MAX)
+    }
+
+    fn to_io(err: rusqlite::Error) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
+
+// Built without SQLite, so there's nothing to open.
+#[cfg(not(feature = "sqlite"))]
+pub struct Store;
+
+#[cfg(not(feature = "sqlite"))]
+impl Store {
+    pub fn open(_: &Path) -> io::Result<Store> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "catalog_database needs grifter built with `--features sqlite`",
+        ))
+    }
+
+    pub fn replace(&self, _: &[Game]) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn update(&self, _: &[Game]) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn game(&self, _: &str) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+
+    pub fn listed_games(&self) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    pub fn search(&self, _: &Query, _: f64) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::search::tests::{search_for, zelda_library};
+    use crate::search::{self, Query};
+
+    #[test]
+    fn finds_the_same_games_as_searching_in_memory() {
+        let games = zelda_library();
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::open(&dir.path().join("catalog.sqlite3")).unwrap();
+        store.replace(&games).unwrap();
+
+        let mut page = search_for("", Some(31), None);
+        page.offset = 1;
+        page.limit = 2;
+        let queries: Vec<Query> = vec![
+            search_for("", None, None),
+            search_for("zelda", None, None),
+            search_for("zelda", Some(12), None),
+            search_for("legnd of zelda", None, None),
+            search_for("ze", None, None),
+            search_for("portl", None, None),
+            search_for("ZELDA  ", None, None),
+            search_for("", Some(31), None),
+            search_for("", Some(31), Some(1)),
+            search_for("link", None, Some(17)),
+            page,
+        ];
+        for query in queries.iter() {
+            let in_memory: Vec<&str> = search::query(&games, query, 0.7)
+                .into_iter()
+                .map(|game| game.slug.as_str())
+                .collect();
+            assert_eq!(
+                store.search(query, 0.7).unwrap(),
+                in_memory,
+                "{}",
+                query.text
+            );
+        }
+
+        // Replacing the games leaves none of the old ones behind.
+        store.replace(&games[..1]).unwrap();
+        assert_eq!(
+            store.search(&search_for("", Some(31), None), 0.7).unwrap(),
+            [games[0].slug.as_str()]
+        );
+    }
+
+    #[test]
+    fn serves_the_saved_games() {
+        let mut games = zelda_library();
+        games[3].hidden = true;
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::open(&dir.path().join("catalog.sqlite3")).unwrap();
+        store.replace(&games).unwrap();
+
+        let json = |game: &Game| serde_json::to_string(game).unwrap();
+        let listed: Vec<String> = games.iter().filter(|g| !g.hidden).map(json).collect();
+        assert_eq!(store.listed_games().unwrap(), listed);
+        // Hidden games can still be looked up on their own.
+        assert_eq!(store.game(&games[3].slug).unwrap(), Some(json(&games[3])));
+        assert_eq!(store.game("missing").unwrap(), None);
+
+        games[0].blake2b = Some("abc".to_string());
+        store.update(&games[..1]).unwrap();
+        assert_eq!(store.game(&games[0].slug).unwrap(), Some(json(&games[0])));
+        assert_eq!(store.listed_games().unwrap()[0], json(&games[0]));
+    }
+}
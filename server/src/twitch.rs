@@ -1,5 +1,6 @@
 use serde::Deserialize;
-use ureq::post;
+use std::fmt;
+use ureq::Agent;
 
 #[derive(Debug, Deserialize)]
 pub struct Authentication {
@@ -12,6 +13,21 @@ pub struct Authentication {
 pub enum Error {
     ClientError(u16, String),
     Other(u16),
+    Request(ureq::Error),
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ClientError(code, message) => write!(f, "{} {}", code, message),
+            Error::Other(code) => write!(f, "unexpected status {}", code),
+            Error::Request(err) => write!(f, "request failed: {}", err),
+            Error::Read(err) => write!(f, "failed to read response: {}", err),
+            Error::Parse(err) => write!(f, "unexpected response: {}", err),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,24 +35,95 @@ pub struct AuthenticationError {
     message: String,
 }
 
-pub fn authenticate(client_id: &str, client_secret: &str) -> Result<Authentication, Error> {
-    let response = post("https://id.twitch.tv/oauth2/token")
+const TWITCH_AUTH_ENDPOINT: &str = "https://id.twitch.tv/oauth2/token";
+
+pub fn authenticate(
+    agent: &Agent,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<Authentication, Error> {
+    authenticate_with_endpoint(agent, TWITCH_AUTH_ENDPOINT, client_id, client_secret)
+}
+
+pub fn authenticate_with_endpoint(
+    agent: &Agent,
+    endpoint: &str,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<Authentication, Error> {
+    let response = agent
+        .post(endpoint)
         .query("client_id", client_id)
         .query("client_secret", client_secret)
         .query("grant_type", "client_credentials")
-        .call()
-        .unwrap();
+        .call();
+    let response = match response {
+        Ok(response) => response,
+        // Twitch puts the reason for a failed login in the body, so hold on to it.
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => return Err(Error::Request(err)),
+    };
 
     match response.status() {
         200 => {
-            let auth = response.into_string().unwrap();
-            let auth = serde_json::from_str::<Authentication>(&auth).unwrap();
-            Ok(auth)
+            let auth = response.into_string().map_err(Error::Read)?;
+            serde_json::from_str::<Authentication>(&auth).map_err(Error::Parse)
         }
         status => {
-            let error = response.into_string().unwrap();
-            let error = serde_json::from_str::<AuthenticationError>(&error).unwrap();
-            Err(Error::ClientError(status, error.message))
+            let error = response.into_string().map_err(Error::Read)?;
+            // Fall back on the whole body when it's not the usual error, like from a proxy.
+            let message = match serde_json::from_str::<AuthenticationError>(&error) {
+                Ok(error) => error.message,
+                Err(_) => error,
+            };
+            Err(Error::ClientError(status, message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use std::time::Duration;
+
+    fn agent() -> Agent {
+        crate::igdb::agent(Duration::from_secs(5), Duration::from_secs(5), 1)
+    }
+
+    #[test]
+    fn authenticates_with_client_credentials() {
+        let server = MockServer::start(vec![(
+            200,
+            r#"{"access_token": "abc", "expires_in": 5000, "token_type": "bearer"}"#,
+        )]);
+        let auth = authenticate_with_endpoint(&agent(), &server.url, "id", "secret").unwrap();
+        assert_eq!(auth.access_token, "abc");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(
+            requests[0].path,
+            "/?client_id=id&client_secret=secret&grant_type=client_credentials"
+        );
+    }
+
+    #[test]
+    fn reports_why_authentication_failed() {
+        let server = MockServer::start(vec![
+            (
+                400,
+                r#"{"status": 400, "message": "invalid client secret"}"#,
+            ),
+            (401, "Unauthorized"),
+        ]);
+        match authenticate_with_endpoint(&agent(), &server.url, "id", "wrong") {
+            Err(Error::ClientError(400, message)) => assert_eq!(message, "invalid client secret"),
+            result => panic!("expected a client error, got {:?}", result),
+        }
+        match authenticate_with_endpoint(&agent(), &server.url, "id", "wrong") {
+            Err(Error::ClientError(401, message)) => assert_eq!(message, "Unauthorized"),
+            result => panic!("expected a client error, got {:?}", result),
         }
     }
 }
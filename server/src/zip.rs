@@ -0,0 +1,470 @@
+use crossbeam_channel::{bounded, Receiver, Sender};
+use flate2::Crc;
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Zip archives written front to back, so they can be sent while they're being made instead of
+// being built in memory or on disk first. Files are stored without compression: games and their
+// extras are almost always compressed already, and it keeps this fast enough to not hold up the
+// download. Sizes and checksums go in a data descriptor after each file since they're only known
+// once it's been copied, and ZIP64 records are used when anything passes 4GB.
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END: u32 = 0x06064b50;
+const ZIP64_END_LOCATOR: u32 = 0x07064b50;
+const END: u32 = 0x06054b50;
+
+// Bit 3 says the sizes and checksum are in a data descriptor, bit 11 that names are UTF-8.
+const FLAGS: u16 = 1 << 3 | 1 << 11;
+const ZIP64_EXTRA: u16 = 0x0001;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+struct Entry {
+    name: String,
+    modified: (u16, u16),
+    crc: u32,
+    size: u64,
+    offset: u64,
+    is_zip64: bool,
+}
+
+pub struct ZipWriter<W: Write> {
+    writer: W,
+    written: u64,
+    entries: Vec<Entry>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ZipWriter {
+            writer,
+            written: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    // `size` is how big the file is expected to be, which decides whether it needs ZIP64 before
+    // any of it is written. Returns how many bytes were actually copied.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        modified: Option<SystemTime>,
+        size: u64,
+        file: &mut impl Read,
+    ) -> io::Result<u64> {
+        let is_zip64 = size >= u32::MAX as u64;
+        let offset = self.written;
+        let modified = dos_date_time(modified.unwrap_or(UNIX_EPOCH));
+
+        let mut header = Vec::new();
+        put_u32(&mut header, LOCAL_HEADER);
+        put_u16(&mut header, if is_zip64 { VERSION_ZIP64 } else { VERSION });
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, 0); // Stored
+        put_u16(&mut header, modified.1);
+        put_u16(&mut header, modified.0);
+        put_u32(&mut header, 0); // The checksum and sizes are in the data descriptor.
+        let placeholder_size = if is_zip64 { u32::MAX } else { 0 };
+        put_u32(&mut header, placeholder_size);
+        put_u32(&mut header, placeholder_size);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, if is_zip64 { 20 } else { 0 });
+        header.extend_from_slice(name.as_bytes());
+        if is_zip64 {
+            put_u16(&mut header, ZIP64_EXTRA);
+            put_u16(&mut header, 16);
+            put_u64(&mut header, 0);
+            put_u64(&mut header, 0);
+        }
+        self.write(&header)?;
+
+        let mut crc = Crc::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            crc.update(&buffer[..read]);
+            self.write(&buffer[..read])?;
+        }
+        let copied = self.written - offset - header.len() as u64;
+        if !is_zip64 && copied >= u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} grew past 4GB while it was being added", name),
+            ));
+        }
+
+        let mut descriptor = Vec::new();
+        put_u32(&mut descriptor, DATA_DESCRIPTOR);
+        put_u32(&mut descriptor, crc.sum());
+        if is_zip64 {
+            put_u64(&mut descriptor, copied);
+            put_u64(&mut descriptor, copied);
+        } else {
+            put_u32(&mut descriptor, copied as u32);
+            put_u32(&mut descriptor, copied as u32);
+        }
+        self.write(&descriptor)?;
+
+        self.entries.push(Entry {
+            name: name.to_string(),
+            modified,
+            crc: crc.sum(),
+            size: copied,
+            offset,
+            is_zip64,
+        });
+        Ok(copied)
+    }
+
+    // Writes the central directory, without which the archive can't be opened.
+    pub fn finish(mut self) -> io::Result<W> {
+        let directory_offset = self.written;
+        let mut directory = Vec::new();
+        for entry in self.entries.iter() {
+            let is_size_too_big = entry.size >= u32::MAX as u64;
+            let is_offset_too_big = entry.offset >= u32::MAX as u64;
+            let mut extra = Vec::new();
+            if is_size_too_big {
+                put_u64(&mut extra, entry.size);
+                put_u64(&mut extra, entry.size);
+            }
+            if is_offset_too_big {
+                put_u64(&mut extra, entry.offset);
+            }
+
+            put_u32(&mut directory, CENTRAL_HEADER);
+            put_u16(&mut directory, VERSION_ZIP64); // Made by
+            let is_zip64 = entry.is_zip64 || !extra.is_empty();
+            put_u16(
+                &mut directory,
+                if is_zip64 { VERSION_ZIP64 } else { VERSION },
+            );
+            put_u16(&mut directory, FLAGS);
+            put_u16(&mut directory, 0); // Stored
+            put_u16(&mut directory, entry.modified.1);
+            put_u16(&mut directory, entry.modified.0);
+            put_u32(&mut directory, entry.crc);
+            let size = u64::min(entry.size, u32::MAX as u64) as u32;
+            put_u32(&mut directory, size);
+            put_u32(&mut directory, size);
+            put_u16(&mut directory, entry.name.len() as u16);
+            put_u16(
+                &mut directory,
+                if extra.is_empty() {
+                    0
+                } else {
+                    extra.len() as u16 + 4
+                },
+            );
+            put_u16(&mut directory, 0); // Comment length
+            put_u16(&mut directory, 0); // Disk number
+            put_u16(&mut directory, 0); // Internal attributes
+            put_u32(&mut directory, 0); // External attributes
+            put_u32(
+                &mut directory,
+                u64::min(entry.offset, u32::MAX as u64) as u32,
+            );
+            directory.extend_from_slice(entry.name.as_bytes());
+            if !extra.is_empty() {
+                put_u16(&mut directory, ZIP64_EXTRA);
+                put_u16(&mut directory, extra.len() as u16);
+                directory.extend_from_slice(&extra);
+            }
+        }
+        let directory_size = directory.len() as u64;
+        let entry_count = self.entries.len() as u64;
+
+        let mut end = Vec::new();
+        let needs_zip64 = entry_count >= u16::MAX as u64
+            || directory_size >= u32::MAX as u64
+            || directory_offset >= u32::MAX as u64;
+        if needs_zip64 {
+            let zip64_end_offset = directory_offset + directory_size;
+            put_u32(&mut end, ZIP64_END);
+            put_u64(&mut end, 44); // Size of the rest of this record
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u32(&mut end, 0); // This disk
+            put_u32(&mut end, 0); // Disk with the central directory
+            put_u64(&mut end, entry_count);
+            put_u64(&mut end, entry_count);
+            put_u64(&mut end, directory_size);
+            put_u64(&mut end, directory_offset);
+
+            put_u32(&mut end, ZIP64_END_LOCATOR);
+            put_u32(&mut end, 0); // Disk with the ZIP64 end record
+            put_u64(&mut end, zip64_end_offset);
+            put_u32(&mut end, 1); // Total disks
+        }
+        put_u32(&mut end, END);
+        put_u16(&mut end, 0); // This disk
+        put_u16(&mut end, 0); // Disk with the central directory
+        let entry_count = u64::min(entry_count, u16::MAX as u64) as u16;
+        put_u16(&mut end, entry_count);
+        put_u16(&mut end, entry_count);
+        put_u32(&mut end, u64::min(directory_size, u32::MAX as u64) as u32);
+        put_u32(&mut end, u64::min(directory_offset, u32::MAX as u64) as u32);
+        put_u16(&mut end, 0); // Comment length
+
+        self.write(&directory)?;
+        self.write(&end)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+// Zip files store times the way MS-DOS did, as (date, time) in local time with 2 second
+// precision. UTC is close enough, and anything before 1980 can't be stored at all.
+fn dos_date_time(time: SystemTime) -> (u16, u16) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    if year < 1980 {
+        return (1 << 5 | 1, 0);
+    }
+    let seconds_of_day = seconds % 86400;
+    let date = ((year - 1980).min(127) as u16) << 9 | (month as u16) << 5 | day as u16;
+    let time = ((seconds_of_day / 3600) as u16) << 11
+        | ((seconds_of_day % 3600 / 60) as u16) << 5
+        | (seconds_of_day % 60 / 2) as u16;
+    (date, time)
+}
+
+// Days since 1970-01-01 to (year, month, day), from Howard Hinnant's date algorithms.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Connects a thread writing an archive to a response reading it. The reader ends when the
+// writer is dropped, and the writer fails once the reader is dropped (like when the download is
+// cancelled) so the thread can stop.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = bounded(16);
+    let writer = PipeWriter {
+        sender,
+        buffer: Vec::with_capacity(PIPE_CHUNK),
+    };
+    let reader = PipeReader {
+        receiver,
+        chunk: Vec::new(),
+        position: 0,
+    };
+    (writer, reader)
+}
+
+const PIPE_CHUNK: usize = 256 * 1024;
+
+pub struct PipeWriter {
+    sender: Sender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= PIPE_CHUNK {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(PIPE_CHUNK));
+        self.sender
+            .send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "download was cancelled"))
+    }
+}
+
+pub struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let read = usize::min(buffer.len(), self.chunk.len() - self.position);
+        buffer[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    fn open(archive: Vec<u8>) -> ::zip::ZipArchive<Cursor<Vec<u8>>> {
+        ::zip::ZipArchive::new(Cursor::new(archive)).unwrap()
+    }
+
+    // Reading a file to the end is what makes the zip crate check its CRC.
+    fn contents(archive: &mut ::zip::ZipArchive<Cursor<Vec<u8>>>, index: usize) -> Vec<u8> {
+        let mut contents = Vec::new();
+        archive
+            .by_index(index)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn archives_can_be_read_back() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let mut writer = ZipWriter::new(Vec::new());
+        let files: [(&str, &[u8]); 3] = [
+            ("Pokémon Red.gb", b"not really a rom"),
+            ("extras/manual.pdf", &[0, 1, 2, 255]),
+            ("empty", b""),
+        ];
+        for (name, contents) in files.iter() {
+            let copied = writer
+                .add_file(
+                    name,
+                    Some(modified),
+                    contents.len() as u64,
+                    &mut &contents[..],
+                )
+                .unwrap();
+            assert_eq!(copied, contents.len() as u64);
+        }
+        let mut archive = open(writer.finish().unwrap());
+
+        assert_eq!(archive.len(), files.len());
+        for (index, (name, expected)) in files.iter().enumerate() {
+            assert_eq!(contents(&mut archive, index), *expected);
+            let file = archive.by_index(index).unwrap();
+            assert_eq!(file.name(), *name);
+            assert_eq!(file.size(), expected.len() as u64);
+            assert_eq!(file.compression(), ::zip::CompressionMethod::Stored);
+            // 2001-09-09 01:46:40 UTC, rounded down to even seconds.
+            let time = file.last_modified();
+            assert_eq!((time.year(), time.month(), time.day()), (2001, 9, 9));
+            assert_eq!((time.hour(), time.minute(), time.second()), (1, 46, 40));
+        }
+    }
+
+    #[test]
+    fn files_expected_to_pass_4gb_use_zip64() {
+        let mut writer = ZipWriter::new(Vec::new());
+        writer
+            .add_file(
+                "big.iso",
+                None,
+                u32::MAX as u64,
+                &mut &b"smaller after all"[..],
+            )
+            .unwrap();
+        writer
+            .add_file("small", None, 5, &mut &b"small"[..])
+            .unwrap();
+        let archive = writer.finish().unwrap();
+        assert_eq!(&archive[4..6], &VERSION_ZIP64.to_le_bytes());
+
+        let mut archive = open(archive);
+        assert_eq!(contents(&mut archive, 0), b"smaller after all");
+        assert_eq!(contents(&mut archive, 1), b"small");
+    }
+
+    #[test]
+    fn too_many_files_for_the_end_record_use_zip64() {
+        let count = u16::MAX as usize + 10;
+        let mut writer = ZipWriter::new(Vec::new());
+        for i in 0..count {
+            let name = format!("{}", i);
+            writer
+                .add_file(&name, None, name.len() as u64, &mut name.as_bytes())
+                .unwrap();
+        }
+        let mut archive = open(writer.finish().unwrap());
+        assert_eq!(archive.len(), count);
+        assert_eq!(contents(&mut archive, count - 1), b"65544");
+    }
+
+    // Writes over 4GB to a temporary file, so it only runs when asked for with `--ignored`.
+    #[test]
+    #[ignore]
+    fn files_past_4gb_use_zip64() {
+        let size = u32::MAX as u64 + 10;
+        let file = tempfile::tempfile().unwrap();
+        let mut writer = ZipWriter::new(io::BufWriter::new(file));
+        let copied = writer
+            .add_file("big.iso", None, size, &mut io::repeat(7).take(size))
+            .unwrap();
+        assert_eq!(copied, size);
+        writer
+            .add_file("after", None, 5, &mut &b"after"[..])
+            .unwrap();
+        let file = writer.finish().unwrap().into_inner().unwrap();
+
+        let mut archive = ::zip::ZipArchive::new(file).unwrap();
+        let mut big = archive.by_index(0).unwrap();
+        assert_eq!(big.size(), size);
+        assert_eq!(io::copy(&mut big, &mut io::sink()).unwrap(), size);
+        drop(big);
+        let mut after = String::new();
+        archive
+            .by_index(1)
+            .unwrap()
+            .read_to_string(&mut after)
+            .unwrap();
+        assert_eq!(after, "after");
+    }
+}